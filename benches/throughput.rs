@@ -0,0 +1,67 @@
+use blowfishj_rs::BlowfishKey;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::time::{Duration, Instant};
+
+const KEY: &[u8] = b"foobar";
+
+fn sample(len: usize) -> Vec<u8> {
+    (0..len).map(|i| i as u8).collect()
+}
+
+/// Returns the fastest of a few runs of `f`, divided by `len`, in nanoseconds per byte.
+fn ns_per_byte(len: usize, mut f: impl FnMut()) -> f64 {
+    let best = (0..5)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or(Duration::ZERO);
+    best.as_nanos() as f64 / len as f64
+}
+
+/// Decrypt must scale like encrypt: both are linear in the input length. A quadratic
+/// decrypt is about 64 times slower per byte at 4 MiB than at 64 KiB.
+fn check_scaling(key: &BlowfishKey) {
+    let (small, large) = (sample(1 << 16), sample(4 << 20));
+    let (small_enc, large_enc) = (key.encrypt(&small).unwrap(), key.encrypt(&large).unwrap());
+
+    let encrypt_growth = ns_per_byte(large.len(), || drop(key.encrypt(&large).unwrap()))
+        / ns_per_byte(small.len(), || drop(key.encrypt(&small).unwrap()));
+    let decrypt_growth = ns_per_byte(large.len(), || drop(key.decrypt(&large_enc).unwrap()))
+        / ns_per_byte(small.len(), || drop(key.decrypt(&small_enc).unwrap()));
+
+    assert!(
+        decrypt_growth < encrypt_growth * 4.0,
+        "decrypt slows down {:.1}x per byte from 64 KiB to 4 MiB, encrypt {:.1}x",
+        decrypt_growth,
+        encrypt_growth
+    );
+}
+
+fn throughput(c: &mut Criterion) {
+    // Build the key schedule once, so that the numbers are for the CTS loops alone.
+    let key = BlowfishKey::new(KEY).unwrap();
+    check_scaling(&key);
+
+    let mut group = c.benchmark_group("cts");
+
+    for len in [1 << 10, 1 << 16, 10 << 20] {
+        let plain = sample(len);
+        let encrypted = key.encrypt(&plain).unwrap();
+
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_with_input(BenchmarkId::new("encrypt", len), &plain, |b, data| {
+            b.iter(|| key.encrypt(data).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("decrypt", len), &encrypted, |b, data| {
+            b.iter(|| key.decrypt(data).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
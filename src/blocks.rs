@@ -0,0 +1,245 @@
+use crate::consts::*;
+use crate::error::Result;
+use std::io::{self, Read, Write};
+use zeroize::Zeroizing;
+
+const CHUNK_SIZE: usize = 8192;
+
+/// The mode-specific part of a [`BlockWriter`].
+pub(crate) trait Encryptor {
+    /// Encrypts whole blocks from `src` into `dst`, which has the same length.
+    fn encrypt(&mut self, src: &[u8], dst: &mut [u8]) -> Result<()>;
+
+    /// Encrypts the last `len` bytes, fewer than a block, into `dst` and returns how many
+    /// bytes to write.
+    fn encrypt_last(&mut self, last: &mut [u8; BLOCK_SIZE], len: usize, dst: &mut [u8]) -> Result<usize>;
+}
+
+/// The mode-specific part of a [`BlockReader`].
+pub(crate) trait Decryptor {
+    /// Returns how many bytes at the start of `pending` can be decrypted now. `eof` is set
+    /// once the inner reader has no more data.
+    fn ready(&mut self, pending: &mut Vec<u8>, eof: bool) -> io::Result<usize>;
+
+    /// Decrypts `src` into `dst`, which has the same length.
+    fn decrypt(&mut self, src: &[u8], dst: &mut [u8]) -> Result<()>;
+
+    /// Finishes the last decrypted data, for example by removing padding.
+    fn finish(&mut self, _plain: &mut Vec<u8>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Encrypts written data block by block, holding a partial block back until `finish`.
+///
+/// Ciphertext is collected in `out` and passed on at the start of the next call, so a
+/// failing inner writer never leaves input half consumed.
+pub(crate) struct BlockWriter<W: Write, E: Encryptor> {
+    inner: Option<W>,
+    encryptor: E,
+    pending: Zeroizing<[u8; BLOCK_SIZE]>,
+    pending_len: usize,
+    out: Vec<u8>,
+}
+
+impl<W: Write, E: Encryptor> BlockWriter<W, E> {
+    pub(crate) fn new(encryptor: E, inner: W) -> Self {
+        Self {
+            inner: Some(inner),
+            encryptor,
+            pending: Zeroizing::new([0; BLOCK_SIZE]),
+            pending_len: 0,
+            out: Vec::with_capacity(CHUNK_SIZE + BLOCK_SIZE),
+        }
+    }
+
+    /// Like `new`, but writes `header` before the ciphertext.
+    pub(crate) fn with_header(encryptor: E, header: &[u8], inner: W) -> Self {
+        let mut writer = Self::new(encryptor, inner);
+        writer.out.extend_from_slice(header);
+        writer
+    }
+
+    pub(crate) fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    /// Encrypts the last block, flushes and returns the inner writer. The inner writer is
+    /// dropped on failure, so that dropping `self` cannot write the last block again.
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        let mut inner = self.inner.take().unwrap();
+        self.finish_prv(&mut inner)?;
+        Ok(inner)
+    }
+
+    fn finish_prv(&mut self, inner: &mut W) -> io::Result<()> {
+        let len = self.pending_len;
+        self.pending_len = 0;
+        let start = self.out.len();
+        self.out.resize(start + BLOCK_SIZE, 0);
+        let n = self.encryptor.encrypt_last(&mut self.pending, len, &mut self.out[start..])?;
+        self.out.truncate(start + n);
+        dump(&mut self.out, inner)?;
+        inner.flush()
+    }
+
+    /// Encrypts `src`, a whole number of blocks, onto the end of `out`.
+    fn encrypt_into_out(&mut self, src: &[u8]) -> Result<()> {
+        let start = self.out.len();
+        self.out.resize(start + src.len(), 0);
+        let result = self.encryptor.encrypt(src, &mut self.out[start..]);
+        if result.is_err() {
+            self.out.truncate(start);
+        }
+        result
+    }
+}
+
+/// Writes all of `out` to `inner`, removing what was written even if a later write fails.
+fn dump<W: Write>(out: &mut Vec<u8>, inner: &mut W) -> io::Result<()> {
+    while !out.is_empty() {
+        match inner.write(out) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                out.drain(..n);
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+impl<W: Write, E: Encryptor> Write for BlockWriter<W, E> {
+    /// Passes on earlier ciphertext, then takes up to one chunk of `data`. Errors are only
+    /// returned before any of `data` is taken.
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        dump(&mut self.out, self.inner.as_mut().unwrap())?;
+
+        if self.pending_len > 0 {
+            let n = data.len().min(BLOCK_SIZE - self.pending_len);
+            let mut block = self.pending.clone();
+            block[self.pending_len..self.pending_len + n].copy_from_slice(&data[..n]);
+            if self.pending_len + n == BLOCK_SIZE {
+                self.encrypt_into_out(&block[..])?;
+                self.pending_len = 0;
+            } else {
+                self.pending_len += n;
+            }
+            self.pending = block;
+            return Ok(n);
+        }
+
+        let full = (data.len() - data.len() % BLOCK_SIZE).min(CHUNK_SIZE);
+        if full > 0 {
+            self.encrypt_into_out(&data[..full])?;
+            return Ok(full);
+        }
+
+        self.pending[..data.len()].copy_from_slice(data);
+        self.pending_len = data.len();
+        Ok(data.len())
+    }
+
+    /// Writes the ciphertext of all full blocks and flushes the inner writer. The last
+    /// block is only written by `finish`.
+    fn flush(&mut self) -> io::Result<()> {
+        let inner = self.inner.as_mut().unwrap();
+        dump(&mut self.out, inner)?;
+        inner.flush()
+    }
+}
+
+impl<W: Write, E: Encryptor> Drop for BlockWriter<W, E> {
+    fn drop(&mut self) {
+        if let Some(mut inner) = self.inner.take() {
+            let _ = self.finish_prv(&mut inner);
+        }
+    }
+}
+
+/// Decrypts data read from the inner reader as soon as the [`Decryptor`] allows.
+pub(crate) struct BlockReader<R: Read, D: Decryptor> {
+    inner: R,
+    decryptor: D,
+    pending: Vec<u8>,
+    plain: Zeroizing<Vec<u8>>,
+    pos: usize,
+    eof: bool,
+}
+
+impl<R: Read, D: Decryptor> BlockReader<R, D> {
+    pub(crate) fn new(decryptor: D, inner: R) -> Self {
+        Self {
+            inner,
+            decryptor,
+            pending: Vec::with_capacity(CHUNK_SIZE + BLOCK_SIZE),
+            plain: Zeroizing::new(Vec::with_capacity(CHUNK_SIZE + BLOCK_SIZE)),
+            pos: 0,
+            eof: false,
+        }
+    }
+
+    pub(crate) fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub(crate) fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        while self.pos == self.plain.len() && !self.eof {
+            let start = self.pending.len();
+            self.pending.resize(start + CHUNK_SIZE, 0);
+            let n = match self.inner.read(&mut self.pending[start..]) {
+                Ok(n) => n,
+                Err(e) => {
+                    self.pending.truncate(start);
+                    return Err(e);
+                }
+            };
+            self.pending.truncate(start + n);
+
+            let len = self.decryptor.ready(&mut self.pending, n == 0)?;
+            self.plain.resize(len, 0);
+            self.decryptor.decrypt(&self.pending[..len], &mut self.plain)?;
+            self.pending.drain(..len);
+            self.pos = 0;
+
+            if n == 0 {
+                self.decryptor.finish(&mut self.plain)?;
+                self.eof = true;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read, D: Decryptor> Read for BlockReader<R, D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.fill()?;
+        let n = buf.len().min(self.plain.len() - self.pos);
+        buf[..n].copy_from_slice(&self.plain[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Returns at most `chunk` bytes per read, to test how the readers handle short reads.
+#[cfg(test)]
+pub(crate) struct ChunkedReader<'a> {
+    pub(crate) data: &'a [u8],
+    pub(crate) chunk: usize,
+}
+
+#[cfg(test)]
+impl Read for ChunkedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.data.len().min(self.chunk).min(buf.len());
+        buf[..n].copy_from_slice(&self.data[..n]);
+        self.data = &self.data[n..];
+        Ok(n)
+    }
+}
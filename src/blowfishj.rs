@@ -1,28 +1,230 @@
-use crate::cts::BlowfishCTS;
-use hex;
-use std::error::Error;
+use crate::charset;
+use crate::consts::*;
+use crate::cts::{random_iv, BlowfishKey};
+use crate::encoding::{decode_text, encode_text};
+use crate::envelope::Envelope;
+use crate::error::{Error, Result};
+use crate::options::{Mode, Options};
+use crate::salted::{self, PasswordHash};
+use encoding_rs::Encoding as Charset;
+use zeroize::Zeroizing;
 
-pub fn encrypt(key: &str, src: &str) -> Result<String, Box<dyn Error>> {
-    let buf = src.as_bytes().to_vec();
-    let mut encrypted_buf = buf.clone();
-    let mut cts = BlowfishCTS::new();
-    cts.initialize(key.as_bytes())?;
-    cts.encrypt(&buf, 0, &mut encrypted_buf, 0, buf.len());
-    Ok(hex::encode_upper(encrypted_buf))
+pub fn encrypt_bytes(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    encrypt_bytes_with(&Options::default(), key, data)
 }
 
-pub fn decrypt(key: &str, src: &str) -> Result<String, Box<dyn Error>> {
-    let buf = hex::decode(src)?;
-    let mut decrypted_buf = buf.clone();
-    let mut cts = BlowfishCTS::new();
-    cts.initialize(key.as_bytes())?;
-    cts.decrypt(&buf, 0, &mut decrypted_buf, 0, buf.len());
-    Ok(String::from_utf8(decrypted_buf)?)
+pub fn decrypt_bytes(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    decrypt_bytes_with(&Options::default(), key, data)
+}
+
+pub fn encrypt<K: AsRef<[u8]>>(key: K, src: &str) -> Result<String> {
+    encrypt_with(&Options::default(), key, src)
+}
+
+pub fn decrypt<K: AsRef<[u8]>>(key: K, src: &str) -> Result<String> {
+    decrypt_with(&Options::default(), key, src)
+}
+
+pub fn encrypt_bytes_with(options: &Options, key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    Cipher::new(options, key)?.encrypt(data)
+}
+
+pub fn decrypt_bytes_with(options: &Options, key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    Cipher::new(options, key)?.decrypt(data)
+}
+
+/// Like [`decrypt_bytes`], but the plaintext is zeroized when it is dropped.
+pub fn decrypt_bytes_zeroizing(key: &[u8], data: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    decrypt_bytes_zeroizing_with(&Options::default(), key, data)
+}
+
+/// Like [`decrypt`], but the plaintext is zeroized when it is dropped. If the plaintext is
+/// not valid UTF-8, [`Error::InvalidUtf8`] still carries the decrypted bytes.
+pub fn decrypt_zeroizing<K: AsRef<[u8]>>(key: K, src: &str) -> Result<Zeroizing<String>> {
+    decrypt_zeroizing_with(&Options::default(), key, src)
+}
+
+pub fn decrypt_bytes_zeroizing_with(options: &Options, key: &[u8], data: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    decrypt_bytes_with(options, key, data).map(Zeroizing::new)
+}
+
+pub fn decrypt_zeroizing_with<K: AsRef<[u8]>>(options: &Options, key: K, src: &str) -> Result<Zeroizing<String>> {
+    let decrypted_buf = decrypt_text(options, key.as_ref(), src)?;
+    Ok(Zeroizing::new(charset::into_string(options, decrypted_buf)?))
+}
+
+/// Encrypts every text with the same key, which is set up only once.
+pub fn encrypt_many<K, I>(key: K, texts: I) -> Result<Vec<String>>
+where
+    K: AsRef<[u8]>,
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    encrypt_many_with(&Options::default(), key, texts)
+}
+
+/// Decrypts every text with the same key, which is set up only once.
+pub fn decrypt_many<K, I>(key: K, texts: I) -> Result<Vec<String>>
+where
+    K: AsRef<[u8]>,
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    decrypt_many_with(&Options::default(), key, texts)
+}
+
+pub fn encrypt_many_with<K, I>(options: &Options, key: K, texts: I) -> Result<Vec<String>>
+where
+    K: AsRef<[u8]>,
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    let cipher = Cipher::new(options, key.as_ref())?;
+    texts
+        .into_iter()
+        .map(|text| encode_text(options, &cipher.encrypt(text.as_ref().as_bytes())?))
+        .collect()
+}
+
+pub fn decrypt_many_with<K, I>(options: &Options, key: K, texts: I) -> Result<Vec<String>>
+where
+    K: AsRef<[u8]>,
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    let cipher = Cipher::new(options, key.as_ref())?;
+    texts
+        .into_iter()
+        .map(|text| {
+            let text = text.as_ref();
+            let decrypted_buf = if salted::is_salted(options, text) {
+                salted::decrypt(options, key.as_ref(), text)?
+            } else {
+                cipher.decrypt(&decode_text(options, text)?)?
+            };
+            charset::into_string(options, decrypted_buf)
+        })
+        .collect()
+}
+
+/// A key set up for the selected mode, ready for any number of messages.
+pub(crate) enum Cipher {
+    Legacy(BlowfishKey),
+    Randomized(BlowfishKey),
+    Authenticated(Envelope),
+}
+
+impl Cipher {
+    pub(crate) fn new(options: &Options, key: &[u8]) -> Result<Self> {
+        Ok(match options.mode {
+            Mode::Legacy => Cipher::Legacy(options.new_key(key)?),
+            Mode::Randomized => Cipher::Randomized(options.new_key(key)?),
+            Mode::Authenticated => Cipher::Authenticated(Envelope::new(options, key)?),
+        })
+    }
+
+    pub(crate) fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Cipher::Legacy(key) => key.encrypt(data),
+            Cipher::Randomized(key) => {
+                let iv = random_iv()?;
+                let mut encrypted_buf = vec![0u8; BLOCK_SIZE + data.len()];
+                encrypted_buf[..BLOCK_SIZE].copy_from_slice(&iv);
+                key.message_with_iv(&iv).encrypt(data, 0, &mut encrypted_buf, BLOCK_SIZE, data.len())?;
+                Ok(encrypted_buf)
+            }
+            Cipher::Authenticated(envelope) => envelope.seal(data),
+        }
+    }
+
+    pub(crate) fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Cipher::Legacy(key) => key.decrypt(data),
+            Cipher::Randomized(key) => {
+                if data.len() < BLOCK_SIZE {
+                    return Err(Error::Truncated);
+                }
+                let iv = data[..BLOCK_SIZE].try_into().unwrap();
+                let len = data.len() - BLOCK_SIZE;
+                let mut decrypted_buf = vec![0u8; len];
+                key.message_with_iv(iv).decrypt(data, BLOCK_SIZE, &mut decrypted_buf, 0, len)?;
+                Ok(decrypted_buf)
+            }
+            Cipher::Authenticated(envelope) => envelope.open(data),
+        }
+    }
+}
+
+pub fn encrypt_with<K: AsRef<[u8]>>(options: &Options, key: K, src: &str) -> Result<String> {
+    let encrypted_buf = encrypt_bytes_with(options, key.as_ref(), src.as_bytes())?;
+    encode_text(options, &encrypted_buf)
+}
+
+/// Decrypts ciphertext from [`encrypt_with`], or with [`Options::detect_salted`] also a
+/// salted string from [`encrypt_salted_with`].
+pub fn decrypt_with<K: AsRef<[u8]>>(options: &Options, key: K, src: &str) -> Result<String> {
+    let decrypted_buf = decrypt_text(options, key.as_ref(), src)?;
+    charset::into_string(options, decrypted_buf)
+}
+
+/// Encrypts `src` with a key derived from the password by `hash` and a random salt. The
+/// result starts with a `$bfj$` header naming the algorithm, its parameters and the salt,
+/// followed by the ciphertext in the selected encoding. [`decrypt_with`] reads it back when
+/// [`Options::detect_salted`] is set. It cannot be read by blowfishj. Parameters that
+/// decrypting would refuse fail with [`Error::InvalidPasswordHash`](crate::Error::InvalidPasswordHash).
+pub fn encrypt_salted<K: AsRef<[u8]>>(hash: PasswordHash, key: K, src: &str) -> Result<String> {
+    encrypt_salted_with(&Options::default(), hash, key, src)
+}
+
+/// Like [`encrypt_salted`]. The derived key is used as the Blowfish key as is, so only the
+/// mode, encoding and weak-key settings of `options` apply. The mode is recorded in the
+/// header, so decrypting does not depend on the mode of the decrypting options.
+pub fn encrypt_salted_with<K: AsRef<[u8]>>(options: &Options, hash: PasswordHash, key: K, src: &str) -> Result<String> {
+    salted::encrypt(options, hash, key.as_ref(), src.as_bytes())
+}
+
+/// Like [`encrypt`], but the password and the text are converted to `charset` first, like
+/// Java's `String.getBytes` with a platform default charset such as Big5 or Windows-1252.
+/// Characters the charset cannot represent give [`Error::UnmappableText`] instead of the `?`
+/// Java would substitute.
+pub fn encrypt_charset(charset: &'static Charset, key: &str, src: &str) -> Result<String> {
+    encrypt_charset_with(&Options::default(), charset, key, src)
+}
+
+/// Like [`decrypt`], but the password is converted to `charset` and the decrypted bytes
+/// are read as `charset`.
+pub fn decrypt_charset(charset: &'static Charset, key: &str, src: &str) -> Result<String> {
+    decrypt_charset_with(&Options::default(), charset, key, src)
+}
+
+pub fn encrypt_charset_with(options: &Options, charset: &'static Charset, key: &str, src: &str) -> Result<String> {
+    let key = Zeroizing::new(charset::encode(charset, key)?.into_owned());
+    let data = Zeroizing::new(charset::encode(charset, src)?.into_owned());
+    let encrypted_buf = encrypt_bytes_with(options, &key, &data)?;
+    encode_text(options, &encrypted_buf)
+}
+
+/// Like [`decrypt_charset`]. With [`Options::lossy`], malformed text is replaced with U+FFFD
+/// instead of failing with [`Error::InvalidText`].
+pub fn decrypt_charset_with(options: &Options, charset: &'static Charset, key: &str, src: &str) -> Result<String> {
+    let key = Zeroizing::new(charset::encode(charset, key)?.into_owned());
+    let decrypted_buf = decrypt_text(options, &key, src)?;
+    charset::decode(options, charset, decrypted_buf)
+}
+
+fn decrypt_text(options: &Options, key: &[u8], src: &str) -> Result<Vec<u8>> {
+    if salted::is_salted(options, src) {
+        salted::decrypt(options, key, src)
+    } else {
+        decrypt_bytes_with(options, key, &decode_text(options, src)?)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*; // Bring your encrypt and decrypt functions into scope
+    use crate::encoding::Encoding;
+    use crate::options::KeyDerivation;
 
     struct Fixture {
         secret: &'static str,
@@ -66,4 +268,211 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_decrypt_errors() {
+        assert_eq!(decrypt("foobar", "5D0"), Err(Error::OddLength));
+        assert_eq!(decrypt("foobar", "5DX9"), Err(Error::InvalidEncoding { c: 'X', index: 2 }));
+
+        match decrypt("wrong", FIXTURES[1].encrypted) {
+            Err(Error::InvalidUtf8(e)) => assert_eq!(e.as_bytes().len(), 24),
+            other => panic!("expected InvalidUtf8, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let key = [0xffu8, 0x00, 0x80, 0x7f];
+        let data: Vec<u8> = (0..=255).collect();
+        for len in [0, 1, 7, 8, 9, 255, 256] {
+            let encrypted = encrypt_bytes(&key, &data[..len]).expect("encrypt_bytes failed");
+            assert_eq!(encrypted.len(), len);
+            let decrypted = decrypt_bytes(&key, &encrypted).expect("decrypt_bytes failed");
+            assert_eq!(decrypted, &data[..len]);
+        }
+    }
+
+    #[test]
+    fn test_bytes_match_string_api() {
+        for fixture in FIXTURES {
+            let got = encrypt_bytes(fixture.secret.as_bytes(), fixture.text.as_bytes()).expect("encrypt_bytes failed");
+            assert_eq!(hex::encode_upper(got), fixture.encrypted);
+        }
+    }
+
+    #[test]
+    fn test_randomized() {
+        let options = Options::new().mode(Mode::Randomized);
+        for fixture in FIXTURES {
+            let first = encrypt_with(&options, fixture.secret, fixture.text).expect("encrypt failed");
+            let second = encrypt_with(&options, fixture.secret, fixture.text).expect("encrypt failed");
+            assert_ne!(first, second);
+            assert_eq!(first.len(), fixture.encrypted.len() + 2 * BLOCK_SIZE);
+
+            for encrypted in [first, second] {
+                let got = decrypt_with(&options, fixture.secret, &encrypted).expect("decrypt failed");
+                assert_eq!(got, fixture.text);
+            }
+        }
+
+        assert_eq!(decrypt_bytes_with(&options, b"foobar", &[0; 7]), Err(Error::Truncated));
+        assert_eq!(decrypt_bytes_with(&options, b"foobar", &[0; 8]), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn test_randomized_with_legacy_iv() {
+        // With the all-ones IV the randomized format is the legacy ciphertext behind the IV.
+        let options = Options::new().mode(Mode::Randomized);
+        let fixture = &FIXTURES[0];
+        let encrypted = format!("{}{}", "FF".repeat(BLOCK_SIZE), fixture.encrypted);
+        assert_eq!(decrypt_with(&options, fixture.secret, &encrypted), Ok(fixture.text.to_string()));
+    }
+
+    #[test]
+    fn test_authenticated() {
+        let options = Options::new().mode(Mode::Authenticated);
+        for fixture in FIXTURES {
+            let encrypted = encrypt_with(&options, fixture.secret, fixture.text).expect("encrypt failed");
+            let got = decrypt_with(&options, fixture.secret, &encrypted).expect("decrypt failed");
+            assert_eq!(got, fixture.text);
+            assert_eq!(decrypt_with(&options, "wrong", &encrypted), Err(Error::AuthenticationFailed));
+        }
+    }
+
+    #[test]
+    fn test_reject_weak_keys() {
+        let text = "weak keys are rejected";
+        assert!(encrypt("weak31364", text).is_ok());
+        for mode in [Mode::Legacy, Mode::Randomized] {
+            let options = Options::new().mode(mode).reject_weak_keys(true);
+            assert_eq!(encrypt_with(&options, "weak31364", text), Err(Error::WeakKey));
+            assert!(encrypt_with(&options, "foobar", text).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_key_derivation() {
+        let text = "How I wish I could recollect PI easily using one trick?";
+        let cases = [
+            (KeyDerivation::Ripemd256, FIXTURES[0].encrypted),
+            (KeyDerivation::Sha1, "73CB58CFE60284D4BFD376BBC67720E52F552029ADF7568D29D25DFC59F6540B2212FE307CCCF5D93CCB1F4AFD19E129060E8CDF022EEE"),
+            (KeyDerivation::Sha256, "6D8F2688921D1E1C72D8B4914F60B75CFF7658B774EDA09B37C90C153AA6E2914B180C95DFF93C078A9A9E15098B431EDBD8A376F92702"),
+            (KeyDerivation::Raw, "3D24069F76B5440706F56348117E3893110E9244BDB0DFE7F7BC778AA180B412C54F2D1ADC988E9FCF94034E6B80CBCD812008CE2F32FA"),
+        ];
+        for (key_derivation, encrypted) in cases {
+            let options = Options::new().key_derivation(key_derivation);
+            assert_eq!(encrypt_with(&options, "foobar", text).as_deref(), Ok(encrypted), "{:?}", key_derivation);
+            assert_eq!(decrypt_with(&options, "foobar", encrypted).as_deref(), Ok(text), "{:?}", key_derivation);
+        }
+    }
+
+    #[test]
+    fn test_raw_key_length() {
+        let options = Options::new().key_derivation(KeyDerivation::Raw);
+        assert_eq!(encrypt_with(&options, "", "text"), Err(Error::EmptyKey));
+        assert_eq!(encrypt_with(&options, "abc", "text"), Err(Error::InvalidKeyLength(3)));
+        assert_eq!(encrypt_with(&options, [0u8; 57], "text"), Err(Error::InvalidKeyLength(57)));
+        assert!(encrypt_with(&options, "abcd", "text").is_ok());
+        assert!(encrypt_with(&options, [0u8; 56], "text").is_ok());
+    }
+
+    #[test]
+    fn test_salted() {
+        let text = "How I wish I could recollect PI easily using one trick?";
+        let hash = PasswordHash::Pbkdf2Sha256 { iterations: 1000 };
+        for mode in [Mode::Legacy, Mode::Randomized, Mode::Authenticated] {
+            let options = Options::new().mode(mode).detect_salted(true);
+            let encrypted = encrypt_salted_with(&options, hash, "foobar", text).unwrap();
+            assert!(encrypted.starts_with("$bfj$pbkdf2-sha256$i=1000$"));
+            assert_eq!(decrypt_with(&options, "foobar", &encrypted).as_deref(), Ok(text));
+            assert_eq!(decrypt_zeroizing_with(&options, "foobar", &encrypted).unwrap().as_str(), text);
+        }
+
+        let encrypted = encrypt_salted(hash, "foobar", text).unwrap();
+        assert_eq!(decrypt("foobar", &encrypted), Err(Error::InvalidEncoding { c: '$', index: 0 }));
+
+        let options = Options::new().detect_salted(true);
+        let mixed = [FIXTURES[0].encrypted, encrypted.as_str()];
+        assert_eq!(decrypt_many_with(&options, "foobar", mixed), Ok(vec![text.to_string(), text.to_string()]));
+    }
+
+    #[test]
+    fn test_encodings() {
+        let text = FIXTURES[0].text;
+        for encoding in [Encoding::UpperHex, Encoding::LowerHex, Encoding::Base64, Encoding::Base64Url] {
+            let options = Options::new().encoding(encoding);
+            let encrypted = encrypt_with(&options, "foobar", text).unwrap();
+            let expected = encoding.encode(&hex::decode(FIXTURES[0].encrypted).unwrap());
+            assert_eq!(encrypted.as_bytes(), expected, "{:?}", encoding);
+            assert_eq!(decrypt_with(&options, "foobar", &encrypted).as_deref(), Ok(text));
+
+            let detect = Options::new().detect_encoding(true);
+            let wrapped = format!("  {}\n{}\n", &encrypted[..20], &encrypted[20..]);
+            assert_eq!(decrypt_with(&detect, "foobar", &wrapped).as_deref(), Ok(text), "{:?}", encoding);
+        }
+
+        let options = Options::new().encoding(Encoding::Raw);
+        assert_eq!(encrypt_with(&options, "foobar", text), Err(Error::NotText));
+        assert_eq!(decrypt_with(&options, "foobar", FIXTURES[0].encrypted), Err(Error::NotText));
+    }
+
+    #[test]
+    fn test_charset() {
+        // 密碼 and 一二三四 one two 3 4 encoded in Big5 by Java's String.getBytes.
+        let encrypted = "212D2F9E98B57384436357E7D0A5CD91B1DC0869";
+        assert_eq!(encrypt_charset(encoding_rs::BIG5, "密碼", "一二三四 one two 3 4").as_deref(), Ok(encrypted));
+        assert_eq!(decrypt_charset(encoding_rs::BIG5, "密碼", encrypted).as_deref(), Ok("一二三四 one two 3 4"));
+        assert!(matches!(decrypt("密碼", encrypted), Err(Error::InvalidUtf8(_))));
+
+        let cases = [
+            (encoding_rs::GBK, "密码", "一二三四 one two 3 4"),
+            (encoding_rs::SHIFT_JIS, "パスワード", "いろはにほへと"),
+            (encoding_rs::WINDOWS_1252, "Pa$$wörd", "Grüße, Café"),
+            (encoding_rs::UTF_16BE, "密碼", "hello 😀"),
+            (encoding_rs::UTF_16LE, "密碼", "hello 😀"),
+        ];
+        for (charset, key, text) in cases {
+            let encrypted = encrypt_charset(charset, key, text).unwrap();
+            assert_eq!(decrypt_charset(charset, key, &encrypted).as_deref(), Ok(text), "{}", charset.name());
+        }
+
+        assert_eq!(
+            encrypt_charset(encoding_rs::WINDOWS_1252, "foobar", "密碼"),
+            Err(Error::UnmappableText { charset: "windows-1252" })
+        );
+        let lossy = Options::new().lossy(true);
+        let encrypted = encrypt_bytes(b"foobar", &[b'o', b'k', 0xff]).map(hex::encode_upper).unwrap();
+        assert_eq!(decrypt_charset(encoding_rs::UTF_8, "foobar", &encrypted), Err(Error::InvalidText { charset: "UTF-8" }));
+        assert_eq!(decrypt_charset_with(&lossy, encoding_rs::UTF_8, "foobar", &encrypted).as_deref(), Ok("ok\u{fffd}"));
+        assert_eq!(decrypt_with(&lossy, "foobar", &encrypted).as_deref(), Ok("ok\u{fffd}"));
+    }
+
+    #[test]
+    fn test_zeroizing() {
+        for fixture in FIXTURES {
+            let got = decrypt_zeroizing(fixture.secret, fixture.encrypted).expect("decrypt failed");
+            assert_eq!(got.as_str(), fixture.text);
+
+            let encrypted = hex::decode(fixture.encrypted).unwrap();
+            let got = decrypt_bytes_zeroizing(fixture.secret.as_bytes(), &encrypted).expect("decrypt failed");
+            assert_eq!(&got[..], fixture.text.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_many() {
+        let texts: Vec<&str> = FIXTURES.iter().map(|f| f.text).collect();
+        let got = encrypt_many("foobar", [texts[0], texts[0]]).expect("encrypt_many failed");
+        assert_eq!(got, [FIXTURES[0].encrypted, FIXTURES[0].encrypted]);
+        assert_eq!(decrypt_many("foobar", &got), Ok(vec![texts[0].to_string(); 2]));
+
+        for mode in [Mode::Legacy, Mode::Randomized, Mode::Authenticated] {
+            let options = Options::new().mode(mode);
+            let encrypted = encrypt_many_with(&options, "密碼", &texts).expect("encrypt_many failed");
+            for (encrypted, text) in encrypted.iter().zip(&texts) {
+                assert_eq!(decrypt_with(&options, "密碼", encrypted).as_deref(), Ok(*text));
+            }
+            assert_eq!(decrypt_many_with(&options, "密碼", &encrypted), Ok(texts.iter().map(|t| t.to_string()).collect()));
+        }
+    }
 }
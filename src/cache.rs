@@ -0,0 +1,228 @@
+use crate::blowfishj::Cipher;
+use crate::charset;
+use crate::encoding::{decode_text, encode_text};
+use crate::error::{Error, Result};
+use crate::options::Options;
+use crate::salted;
+use hmac::{Hmac, Mac};
+use lru::LruCache;
+use sha2::Sha256;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use zeroize::{Zeroize, Zeroizing};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// A bounded LRU cache of expanded keys, for workloads that use the same few passwords
+/// over and over. The keys are set up for the mode of the cache's [`Options`], so
+/// [`Mode::Randomized`](crate::Mode::Randomized) still draws a fresh IV for every message
+/// and [`Mode::Authenticated`](crate::Mode::Authenticated) still writes an envelope.
+///
+/// Passwords are never stored: entries are looked up by an HMAC-SHA256 of the password
+/// under a random key drawn when the cache is created. Evicted keys are zeroized once the
+/// last `Arc` to them is dropped. The cache is `Send + Sync` and can be shared between
+/// threads.
+pub struct KeyCache {
+    options: Options,
+    hash_key: Zeroizing<[u8; 32]>,
+    entries: Mutex<LruCache<[u8; 32], Arc<Cipher>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Hit and miss counters of a [`KeyCache`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl KeyCache {
+    pub fn new(capacity: NonZeroUsize) -> Result<Self> {
+        Self::with_options(capacity, Options::default())
+    }
+
+    /// Creates a cache whose keys are set up with `options`, for example to select a mode or
+    /// to reject weak keys.
+    pub fn with_options(capacity: NonZeroUsize, options: Options) -> Result<Self> {
+        let mut hash_key = Zeroizing::new([0u8; 32]);
+        getrandom::fill(&mut hash_key[..]).map_err(|_| Error::RandomUnavailable)?;
+        Ok(Self {
+            options,
+            hash_key,
+            entries: Mutex::new(LruCache::new(capacity)),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+
+    /// Returns the cipher for `key`, setting it up on a miss.
+    fn get(&self, key: &[u8]) -> Result<Arc<Cipher>> {
+        let mut id = self.id(key);
+
+        if let Some(found) = self.entries.lock().unwrap().get(&id) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            id.zeroize();
+            return Ok(found.clone());
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let expanded = Arc::new(Cipher::new(&self.options, key)?);
+        if let Some((mut evicted, _)) = self.entries.lock().unwrap().push(id, expanded.clone()) {
+            evicted.zeroize();
+        }
+        id.zeroize();
+        Ok(expanded)
+    }
+
+    /// Like [`encrypt_with`](crate::encrypt_with), with the key taken from the cache.
+    pub fn encrypt<K: AsRef<[u8]>>(&self, key: K, src: &str) -> Result<String> {
+        let encrypted_buf = self.get(key.as_ref())?.encrypt(src.as_bytes())?;
+        encode_text(&self.options, &encrypted_buf)
+    }
+
+    /// Like [`decrypt_with`](crate::decrypt_with), with the key taken from the cache. Salted strings
+    /// have a different key for every message and bypass the cache.
+    pub fn decrypt<K: AsRef<[u8]>>(&self, key: K, src: &str) -> Result<String> {
+        if salted::is_salted(&self.options, src) {
+            return charset::into_string(&self.options, salted::decrypt(&self.options, key.as_ref(), src)?);
+        }
+        let buf = decode_text(&self.options, src)?;
+        let decrypted_buf = self.get(key.as_ref())?.decrypt(&buf)?;
+        charset::into_string(&self.options, decrypted_buf)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> NonZeroUsize {
+        self.entries.lock().unwrap().cap()
+    }
+
+    /// Drops every cached key. The counters are kept.
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        while let Some((mut id, _)) = entries.pop_lru() {
+            id.zeroize();
+        }
+    }
+
+    fn id(&self, key: &[u8]) -> [u8; 32] {
+        let mut mac = HmacSha256::new_from_slice(&self.hash_key[..]).expect("HMAC accepts keys of any length");
+        mac.update(key);
+        mac.finalize().into_bytes().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blowfishj::{decrypt_with, encrypt_with};
+    use crate::options::Mode;
+
+    const TEXT: &str = "How I wish I could recollect PI easily using one trick?";
+    const ENCRYPTED: &str = "5D09840C5A0E7A196D949FC41012E27913C5E752AF38136C5ABDD2603B7F2A92198983B6DB7098C063E08D0AECA2891423FBAE3DE636A2";
+
+    fn cache(capacity: usize) -> KeyCache {
+        KeyCache::new(NonZeroUsize::new(capacity).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_hits_and_misses() {
+        let cache = cache(2);
+        assert_eq!(cache.encrypt("foobar", TEXT).as_deref(), Ok(ENCRYPTED));
+        assert_eq!(cache.decrypt("foobar", ENCRYPTED).as_deref(), Ok(TEXT));
+        assert_eq!(cache.decrypt(b"foobar", ENCRYPTED).as_deref(), Ok(TEXT));
+        assert_eq!(cache.stats(), CacheStats { hits: 2, misses: 1 });
+        assert_eq!(cache.len(), 1);
+
+        let first = cache.get(b"foobar").unwrap();
+        let second = cache.get(b"foobar").unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+    }
+
+    #[test]
+    fn test_eviction() {
+        let cache = cache(2);
+        cache.get(b"one").unwrap();
+        cache.get(b"two").unwrap();
+        cache.get(b"one").unwrap();
+        cache.get(b"three").unwrap();
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.capacity().get(), 2);
+
+        // "two" was the least recently used and has been evicted.
+        cache.get(b"one").unwrap();
+        cache.get(b"two").unwrap();
+        assert_eq!(cache.stats(), CacheStats { hits: 2, misses: 4 });
+
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_options() {
+        let options = Options::new().reject_weak_keys(true);
+        let cache = KeyCache::with_options(NonZeroUsize::new(1).unwrap(), options).unwrap();
+        assert!(matches!(cache.get(b"weak31364"), Err(Error::WeakKey)));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_modes() {
+        let capacity = NonZeroUsize::new(1).unwrap();
+
+        let legacy = KeyCache::with_options(capacity, Options::new().mode(Mode::Legacy)).unwrap();
+        assert_eq!(legacy.encrypt("foobar", TEXT).as_deref(), Ok(ENCRYPTED));
+
+        let options = Options::new().mode(Mode::Randomized);
+        let randomized = KeyCache::with_options(capacity, options).unwrap();
+        let first = randomized.encrypt("foobar", TEXT).unwrap();
+        let second = randomized.encrypt("foobar", TEXT).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(first.len(), 2 * (8 + TEXT.len()));
+        assert_eq!(decrypt_with(&options, "foobar", &first).as_deref(), Ok(TEXT));
+        assert_eq!(randomized.decrypt("foobar", &second).as_deref(), Ok(TEXT));
+
+        let options = Options::new().mode(Mode::Authenticated);
+        let authenticated = KeyCache::with_options(capacity, options).unwrap();
+        let sealed = authenticated.encrypt("foobar", TEXT).unwrap();
+        assert_eq!(decrypt_with(&options, "foobar", &sealed).as_deref(), Ok(TEXT));
+        let sealed = encrypt_with(&options, "foobar", TEXT).unwrap();
+        assert_eq!(authenticated.decrypt("foobar", &sealed).as_deref(), Ok(TEXT));
+        let tampered = format!("{}{}", &sealed[..sealed.len() - 1], if sealed.ends_with('0') { '1' } else { '0' });
+        assert_eq!(authenticated.decrypt("foobar", &tampered), Err(Error::AuthenticationFailed));
+        assert_eq!(authenticated.stats(), CacheStats { hits: 2, misses: 1 });
+    }
+
+    #[test]
+    fn test_shared() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<KeyCache>();
+
+        let cache = Arc::new(cache(4));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let cache = cache.clone();
+                std::thread::spawn(move || cache.decrypt("foobar", ENCRYPTED).unwrap())
+            })
+            .collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), TEXT);
+        }
+        assert_eq!(cache.stats().hits + cache.stats().misses, 4);
+    }
+}
@@ -0,0 +1,156 @@
+use crate::consts::*;
+use crate::ecb::{check_offsets, BlowfishECB};
+use crate::error::Result;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// Blowfish in CBC mode, a port of blowfishj's `BlowfishCBC`.
+///
+/// The key is used as is, without hashing. The IV starts at zero and is chained across
+/// calls, so a message can be encrypted in several pieces; [`set_iv`](Self::set_iv) starts
+/// the next message. Lengths must be whole blocks; no padding is added.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct BlowfishCBC {
+    ecb: BlowfishECB,
+    iv: [u8; BLOCK_SIZE],
+}
+
+impl BlowfishCBC {
+    /// Creates an instance keyed with `key`, with the IV set to zero.
+    pub fn with_key(key: &[u8]) -> Result<Self> {
+        Ok(Self::from_ecb(BlowfishECB::with_key(key)?, [0; BLOCK_SIZE]))
+    }
+
+    pub fn with_key_and_iv(key: &[u8], iv: u64) -> Result<Self> {
+        let mut cbc = Self::with_key(key)?;
+        cbc.set_iv(iv);
+        Ok(cbc)
+    }
+
+    pub(crate) fn from_ecb(ecb: BlowfishECB, iv: [u8; BLOCK_SIZE]) -> Self {
+        Self { ecb, iv }
+    }
+
+    /// Sets up the key schedule and resets the IV to zero.
+    pub fn initialize(&mut self, key: &[u8], ofs: usize, len: usize) -> Result<()> {
+        self.ecb.initialize(key, ofs, len)?;
+        self.iv = [0; BLOCK_SIZE];
+        Ok(())
+    }
+
+    /// Returns the current IV, which is the last ciphertext block after encrypting or
+    /// decrypting (`getCBCIV`).
+    pub fn iv(&self) -> u64 {
+        u64::from_be_bytes(self.iv)
+    }
+
+    pub fn iv_bytes(&self) -> [u8; BLOCK_SIZE] {
+        self.iv
+    }
+
+    /// Sets the IV (`setCBCIV`). Bytes are read big-endian, as in blowfishj.
+    pub fn set_iv(&mut self, iv: u64) {
+        self.iv = iv.to_be_bytes();
+    }
+
+    pub fn set_iv_bytes(&mut self, iv: &[u8; BLOCK_SIZE]) {
+        self.iv = *iv;
+    }
+
+    /// Encrypts `len` bytes and returns `len`. A `len` that is not a multiple of
+    /// [`BLOCK_SIZE`] fails with [`Error::NotBlockAligned`](crate::Error::NotBlockAligned)
+    /// instead of skipping the partial block as blowfishj does.
+    pub fn encrypt(&mut self, inbuf: &[u8], inpos: usize, outbuf: &mut [u8], outpos: usize, len: usize) -> Result<usize> {
+        check_offsets(inbuf, inpos, outbuf, outpos, len)?;
+
+        for i in (0..len).step_by(BLOCK_SIZE) {
+            for (j, b) in self.iv.iter_mut().enumerate() {
+                *b ^= inbuf[inpos + i + j];
+            }
+            self.ecb.encrypt_block(&mut self.iv);
+            outbuf[outpos + i..outpos + i + BLOCK_SIZE].copy_from_slice(&self.iv);
+        }
+        Ok(len)
+    }
+
+    /// Decrypts `len` bytes, which must be whole blocks, and returns `len`. On error the IV
+    /// is left as it was.
+    pub fn decrypt(&mut self, inbuf: &[u8], inpos: usize, outbuf: &mut [u8], outpos: usize, len: usize) -> Result<usize> {
+        check_offsets(inbuf, inpos, outbuf, outpos, len)?;
+
+        let mut block = [0u8; BLOCK_SIZE];
+        for i in (0..len).step_by(BLOCK_SIZE) {
+            let cipher: [u8; BLOCK_SIZE] = inbuf[inpos + i..inpos + i + BLOCK_SIZE].try_into().unwrap();
+            block.copy_from_slice(&cipher);
+            self.ecb.decrypt_block(&mut block);
+            for (j, b) in block.iter().enumerate() {
+                outbuf[outpos + i + j] = b ^ self.iv[j];
+            }
+            self.iv = cipher;
+        }
+        block.zeroize();
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    // Blowfish/CBC/NoPadding from the JCE, which matches blowfishj's BlowfishCBC.
+    const PLAIN: &[u8] = b"7654321 Now is the time for all men! and women.";
+    const CIPHER: &str = "6B77B4D63006DEE605B156E27403979358DEB9E7154616D9E852A2469E5D917BB5E0B0D75BA928C0";
+
+    #[test]
+    fn test_java_vector() {
+        let mut cbc = BlowfishCBC::with_key_and_iv(&CBC_KEY, CBC_IV).unwrap();
+        let mut out = [0u8; 48];
+        assert_eq!(cbc.encrypt(PLAIN, 0, &mut out, 0, 40), Ok(40));
+        assert_eq!(hex::encode_upper(&out[..40]), CIPHER);
+        assert_eq!(cbc.iv_bytes()[..], out[32..40]);
+
+        let mut plain = [0u8; 40];
+        cbc.set_iv(CBC_IV);
+        assert_eq!(cbc.decrypt(&out, 0, &mut plain, 0, 40), Ok(40));
+        assert_eq!(plain[..], PLAIN[..40]);
+    }
+
+    #[test]
+    fn test_chained_calls() {
+        let mut whole = BlowfishCBC::with_key_and_iv(&CBC_KEY, CBC_IV).unwrap();
+        let mut expected = [0u8; 40];
+        whole.encrypt(PLAIN, 0, &mut expected, 0, 40).unwrap();
+
+        let mut pieces = BlowfishCBC::with_key(&CBC_KEY).unwrap();
+        pieces.set_iv_bytes(&CBC_IV.to_be_bytes());
+        let mut out = [0u8; 44];
+        assert_eq!(pieces.encrypt(PLAIN, 0, &mut out, 4, 8), Ok(8));
+        assert_eq!(pieces.encrypt(PLAIN, 8, &mut out, 12, 16), Ok(16));
+        assert_eq!(pieces.encrypt(PLAIN, 24, &mut out, 28, 16), Ok(16));
+        assert_eq!(out[4..], expected);
+        assert_eq!(pieces.iv(), whole.iv());
+    }
+
+    #[test]
+    fn test_iv() {
+        let mut cbc = BlowfishCBC::with_key(&CBC_KEY).unwrap();
+        assert_eq!(cbc.iv(), 0);
+        cbc.set_iv(CBC_IV);
+        assert_eq!(cbc.iv_bytes(), [0xfe, 0xdc, 0xba, 0x98, 0x76, 0x54, 0x32, 0x10]);
+        cbc.initialize(&CBC_KEY, 0, CBC_KEY.len()).unwrap();
+        assert_eq!(cbc.iv(), 0);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut cbc = BlowfishCBC::with_key(&CBC_KEY).unwrap();
+        let mut out = [0u8; 8];
+        assert_eq!(cbc.encrypt(PLAIN, 0, &mut out, 0, 7), Err(Error::NotBlockAligned(7)));
+        assert_eq!(cbc.decrypt(PLAIN, 0, &mut out, 0, 12), Err(Error::NotBlockAligned(12)));
+        assert_eq!(cbc.iv(), 0);
+        assert_eq!(cbc.encrypt(PLAIN, 0, &mut out, 0, 16), Err(Error::BufferTooSmall { needed: 16, available: 8 }));
+        assert_eq!(cbc.decrypt(&out, 4, &mut [0u8; 8], 0, 8), Err(Error::BufferTooSmall { needed: 12, available: 8 }));
+        assert_eq!(BlowfishCBC::with_key(b"").err(), Some(Error::EmptyKey));
+        assert_eq!(cbc.initialize(&[1, 2], 5, 3), Err(Error::BufferTooSmall { needed: 8, available: 2 }));
+    }
+}
@@ -0,0 +1,243 @@
+use crate::blocks::{BlockReader, BlockWriter, Decryptor, Encryptor};
+use crate::cbc::BlowfishCBC;
+use crate::consts::*;
+use crate::cts::random_iv;
+use crate::error::{Error, Result};
+use sha1::{Digest, Sha1};
+use std::io::{self, Read, Write};
+use zeroize::Zeroizing;
+
+fn new_cbc(key: &[u8]) -> Result<BlowfishCBC> {
+    let hash = Zeroizing::new(<[u8; 20]>::from(Sha1::digest(key)));
+    BlowfishCBC::with_key(&hash[..])
+}
+
+fn invalid_data(e: Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+impl Encryptor for BlowfishCBC {
+    fn encrypt(&mut self, src: &[u8], dst: &mut [u8]) -> Result<()> {
+        self.encrypt(src, 0, dst, 0, src.len())?;
+        Ok(())
+    }
+
+    /// Pads with 1 to 8 bytes that each hold the number of pad bytes.
+    fn encrypt_last(&mut self, last: &mut [u8; BLOCK_SIZE], len: usize, dst: &mut [u8]) -> Result<usize> {
+        last[len..].fill((BLOCK_SIZE - len) as u8);
+        self.encrypt(&last[..], 0, dst, 0, BLOCK_SIZE)
+    }
+}
+
+struct StreamDecryptor {
+    cbc: BlowfishCBC,
+    has_iv: bool,
+}
+
+impl Decryptor for StreamDecryptor {
+    fn ready(&mut self, pending: &mut Vec<u8>, eof: bool) -> io::Result<usize> {
+        if !self.has_iv {
+            if pending.len() < BLOCK_SIZE {
+                return if eof { Err(invalid_data(Error::Truncated)) } else { Ok(0) };
+            }
+            self.cbc.set_iv_bytes(pending[..BLOCK_SIZE].try_into().unwrap());
+            pending.drain(..BLOCK_SIZE);
+            self.has_iv = true;
+        }
+
+        // Keep the last whole block back until we know whether it is the final one.
+        if eof {
+            if pending.len() != BLOCK_SIZE {
+                return Err(invalid_data(Error::Truncated));
+            }
+            Ok(BLOCK_SIZE)
+        } else {
+            Ok((pending.len().saturating_sub(1) / BLOCK_SIZE) * BLOCK_SIZE)
+        }
+    }
+
+    fn decrypt(&mut self, src: &[u8], dst: &mut [u8]) -> Result<()> {
+        self.cbc.decrypt(src, 0, dst, 0, src.len())?;
+        Ok(())
+    }
+
+    fn finish(&mut self, plain: &mut Vec<u8>) -> io::Result<()> {
+        let len = plain.len();
+        let pad = plain[len - 1] as usize;
+        if pad == 0 || pad > BLOCK_SIZE {
+            return Err(invalid_data(Error::InvalidPadding));
+        }
+        plain.truncate(len - pad);
+        Ok(())
+    }
+}
+
+/// Writes the file format of blowfishj's `BlowfishOutputStream`.
+///
+/// The key is the SHA-1 hash of the key bytes. The output is a random 8-byte IV followed
+/// by the data in CBC mode, with the last block padded by 1 to 8 bytes that each hold the
+/// number of pad bytes. The padding is only written by [`finish`](Self::finish). Dropping
+/// the writer finishes it as well but ignores any error, so call `finish` to see them.
+pub struct BlowfishOutputStreamWriter<W: Write>(BlockWriter<W, BlowfishCBC>);
+
+impl<W: Write> BlowfishOutputStreamWriter<W> {
+    pub fn new(key: &[u8], inner: W) -> Result<Self> {
+        Self::with_iv(key, u64::from_be_bytes(random_iv()?), inner)
+    }
+
+    /// Starts the stream with the given IV instead of a random one, for example to compare
+    /// the output with a file written by blowfishj.
+    pub fn with_iv(key: &[u8], iv: u64, inner: W) -> Result<Self> {
+        let mut cbc = new_cbc(key)?;
+        cbc.set_iv(iv);
+        Ok(Self(BlockWriter::with_header(cbc, &iv.to_be_bytes(), inner)))
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.0.get_ref()
+    }
+
+    /// Writes the padded last block, flushes and returns the inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        self.0.finish()
+    }
+}
+
+impl<W: Write> Write for BlowfishOutputStreamWriter<W> {
+    /// Takes some of `data` and returns how much. On error, none of it was taken.
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.0.write(data)
+    }
+
+    /// Flushes the inner writer. The last block is only written by `finish`.
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Reads the file format of blowfishj's `BlowfishInputStream`, as written by
+/// [`BlowfishOutputStreamWriter`].
+///
+/// The last block is held back until the inner reader reports end of file, so that its
+/// padding can be removed. A stream without a whole IV and data block, or whose length is
+/// not a multiple of the block size, fails with [`Error::Truncated`], and a last byte
+/// outside 1 to 8 with [`Error::InvalidPadding`], both as [`io::ErrorKind::InvalidData`].
+pub struct BlowfishInputStreamReader<R: Read>(BlockReader<R, StreamDecryptor>);
+
+impl<R: Read> BlowfishInputStreamReader<R> {
+    pub fn new(key: &[u8], inner: R) -> Result<Self> {
+        let decryptor = StreamDecryptor { cbc: new_cbc(key)?, has_iv: false };
+        Ok(Self(BlockReader::new(decryptor, inner)))
+    }
+
+    pub fn get_ref(&self) -> &R {
+        self.0.get_ref()
+    }
+
+    pub fn into_inner(self) -> R {
+        self.0.into_inner()
+    }
+}
+
+impl<R: Read> Read for BlowfishInputStreamReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::ChunkedReader;
+
+    const KEY: &[u8] = b"foobar";
+    const TEXT: &[u8] = b"How I wish I could recollect PI easily using one trick?";
+    const IV: u64 = 0x0123456789abcdef;
+
+    // Produced in Java with the JCE's SHA-1 and Blowfish/CBC/PKCS5Padding, which is what
+    // BlowfishOutputStream writes.
+    const FIXTURES: &[(usize, &str)] = &[
+        (0, "0123456789ABCDEFE05E3CB634B5F6DE"),
+        (5, "0123456789ABCDEFFAA3DE5EE77BA169"),
+        (8, "0123456789ABCDEF78E422155354CC63D8E4697934EB344F"),
+        (21, "0123456789ABCDEF78E422155354CC633CF6ED2F96E21280688E0C2E11AB55B0"),
+        (55, "0123456789ABCDEF78E422155354CC633CF6ED2F96E2128070257C992034D658ED5A915535B5C24946D1CB6549AF2AE12ED7C944811C43949ECA931DE926AADA"),
+    ];
+
+    fn read_all(data: &[u8], chunk: usize) -> io::Result<Vec<u8>> {
+        let mut reader = BlowfishInputStreamReader::new(KEY, ChunkedReader { data, chunk }).unwrap();
+        let mut got = Vec::new();
+        reader.read_to_end(&mut got)?;
+        Ok(got)
+    }
+
+    #[test]
+    fn test_writer_fixtures() {
+        for &(len, expected) in FIXTURES {
+            for chunk in [1, 3, 8, 13, 100] {
+                let mut writer = BlowfishOutputStreamWriter::with_iv(KEY, IV, Vec::new()).unwrap();
+                for part in TEXT[..len].chunks(chunk) {
+                    writer.write_all(part).unwrap();
+                }
+                assert_eq!(hex::encode_upper(writer.finish().unwrap()), expected, "len {} chunk {}", len, chunk);
+            }
+        }
+    }
+
+    #[test]
+    fn test_reader_fixtures() {
+        for &(len, encrypted) in FIXTURES {
+            let encrypted = hex::decode(encrypted).unwrap();
+            for chunk in [1, 3, 8, 13, 100] {
+                assert_eq!(read_all(&encrypted, chunk).unwrap(), TEXT[..len], "len {} chunk {}", len, chunk);
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data: Vec<u8> = (0..20000).map(|i| (i * 31 + 7) as u8).collect();
+        let mut out = Vec::new();
+        {
+            let mut writer = BlowfishOutputStreamWriter::new(KEY, &mut out).unwrap();
+            writer.write_all(&data).unwrap();
+        }
+        assert_eq!(out.len(), BLOCK_SIZE + data.len() + BLOCK_SIZE);
+        assert_eq!(read_all(&out, 4096).unwrap(), data);
+    }
+
+    #[test]
+    fn test_corrupt_streams() {
+        let encrypted = hex::decode(FIXTURES[3].1).unwrap();
+        for len in [0, 5, 8, 20, encrypted.len() - 1] {
+            let e = read_all(&encrypted[..len], 100).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData, "len {}", len);
+        }
+
+        let mut reader = BlowfishInputStreamReader::new(b"wrong", &encrypted[..]).unwrap();
+        let e = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(e.into_inner().unwrap().downcast::<Error>().ok().map(|e| *e), Some(Error::InvalidPadding));
+    }
+
+    struct FailingFlush(Vec<u8>);
+
+    impl Write for FailingFlush {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.0.extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::ErrorKind::Other.into())
+        }
+    }
+
+    #[test]
+    fn test_failed_finish_pads_once() {
+        let mut inner = FailingFlush(Vec::new());
+        let mut writer = BlowfishOutputStreamWriter::with_iv(KEY, IV, &mut inner).unwrap();
+        writer.write_all(&TEXT[..5]).unwrap();
+        assert!(writer.finish().is_err());
+        assert_eq!(hex::encode_upper(&inner.0), FIXTURES[1].1);
+    }
+}
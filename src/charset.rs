@@ -0,0 +1,81 @@
+use crate::error::{Error, Result};
+use crate::options::Options;
+use encoding_rs::{Encoding as Charset, UTF_16BE, UTF_16LE};
+use std::borrow::Cow;
+use zeroize::Zeroizing;
+
+/// Converts `src` to `charset`, failing instead of substituting characters the charset
+/// cannot represent. UTF-16 is written without a byte order mark, like Java's `UTF-16BE`
+/// and `UTF-16LE`, where encoding_rs itself would fall back to UTF-8.
+pub(crate) fn encode<'a>(charset: &'static Charset, src: &'a str) -> Result<Cow<'a, [u8]>> {
+    if charset == UTF_16BE || charset == UTF_16LE {
+        let big_endian = charset == UTF_16BE;
+        let encoded = src
+            .encode_utf16()
+            .flat_map(|unit| if big_endian { unit.to_be_bytes() } else { unit.to_le_bytes() })
+            .collect();
+        return Ok(Cow::Owned(encoded));
+    }
+
+    let (encoded, _, unmappable) = charset.encode(src);
+    if unmappable {
+        if let Cow::Owned(mut encoded) = encoded {
+            zeroize::Zeroize::zeroize(&mut encoded);
+        }
+        return Err(Error::UnmappableText { charset: charset.name() });
+    }
+    Ok(encoded)
+}
+
+/// Converts decrypted bytes from `charset` to a `String`, replacing malformed sequences
+/// with U+FFFD if `options` allows lossy decoding.
+pub(crate) fn decode(options: &Options, charset: &'static Charset, buf: Vec<u8>) -> Result<String> {
+    let buf = Zeroizing::new(buf);
+    match charset.decode_without_bom_handling_and_without_replacement(&buf) {
+        Some(text) => Ok(text.into_owned()),
+        None if options.lossy => Ok(charset.decode_without_bom_handling(&buf).0.into_owned()),
+        None => Err(Error::InvalidText { charset: charset.name() }),
+    }
+}
+
+/// Converts decrypted bytes to a `String`, replacing invalid UTF-8 with U+FFFD if `options`
+/// allows lossy decoding.
+pub(crate) fn into_string(options: &Options, buf: Vec<u8>) -> Result<String> {
+    match String::from_utf8(buf) {
+        Ok(text) => Ok(text),
+        Err(e) if options.lossy => {
+            let buf = Zeroizing::new(e.into_bytes());
+            Ok(String::from_utf8_lossy(&buf).into_owned())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{BIG5, SHIFT_JIS, WINDOWS_1252};
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode(BIG5, "密碼").unwrap()[..], [0xb1, 0x4b, 0xbd, 0x58]);
+        assert_eq!(encode(SHIFT_JIS, "パス").unwrap()[..], [0x83, 0x70, 0x83, 0x58]);
+        assert_eq!(encode(WINDOWS_1252, "Café").unwrap()[..], *b"Caf\xe9");
+        assert_eq!(encode(WINDOWS_1252, "密碼"), Err(Error::UnmappableText { charset: "windows-1252" }));
+        assert_eq!(encode(UTF_16BE, "密a😀").unwrap()[..], [0x5b, 0xc6, 0x00, 0x61, 0xd8, 0x3d, 0xde, 0x00]);
+        assert_eq!(encode(UTF_16LE, "密a😀").unwrap()[..], [0xc6, 0x5b, 0x61, 0x00, 0x3d, 0xd8, 0x00, 0xde]);
+    }
+
+    #[test]
+    fn test_decode() {
+        let strict = Options::new();
+        let lossy = Options::new().lossy(true);
+        assert_eq!(decode(&strict, BIG5, vec![0xb1, 0x4b, 0xbd, 0x58]).as_deref(), Ok("密碼"));
+        assert_eq!(decode(&strict, BIG5, vec![0xb1]), Err(Error::InvalidText { charset: "Big5" }));
+        assert_eq!(decode(&lossy, BIG5, vec![b'a', 0xb1]).as_deref(), Ok("a\u{fffd}"));
+        assert_eq!(decode(&strict, UTF_16BE, vec![0x5b, 0xc6, 0x00, 0x61]).as_deref(), Ok("密a"));
+
+        assert!(matches!(into_string(&strict, vec![b'a', 0xff]), Err(Error::InvalidUtf8(_))));
+        assert_eq!(into_string(&lossy, vec![b'a', 0xff]).as_deref(), Ok("a\u{fffd}"));
+    }
+}
@@ -167,3 +167,65 @@ pub static SBOX4_INIT: [u32; SBOX_ENTRIES] = [
     0x02fb8a8c, 0x01c36ae4, 0xd6ebe1f9, 0x90d4f869, 0xa65cdea0, 0x3f09252d, 0xc208e69f,
     0xb74e6132, 0xce77e25b, 0x578fdfe3, 0x3ac372e6,
 ];
+
+// Eric Young's test vectors from SSLeay: (key, plaintext, ciphertext).
+pub static ECB_VECTORS: [(u64, u64, u64); 34] = [
+    (0x0000000000000000, 0x0000000000000000, 0x4ef997456198dd78),
+    (0xffffffffffffffff, 0xffffffffffffffff, 0x51866fd5b85ecb8a),
+    (0x3000000000000000, 0x1000000000000001, 0x7d856f9a613063f2),
+    (0x1111111111111111, 0x1111111111111111, 0x2466dd878b963c9d),
+    (0x0123456789abcdef, 0x1111111111111111, 0x61f9c3802281b096),
+    (0x1111111111111111, 0x0123456789abcdef, 0x7d0cc630afda1ec7),
+    (0x0000000000000000, 0x0000000000000000, 0x4ef997456198dd78),
+    (0xfedcba9876543210, 0x0123456789abcdef, 0x0aceab0fc6a0a28d),
+    (0x7ca110454a1a6e57, 0x01a1d6d039776742, 0x59c68245eb05282b),
+    (0x0131d9619dc1376e, 0x5cd54ca83def57da, 0xb1b8cc0b250f09a0),
+    (0x07a1133e4a0b2686, 0x0248d43806f67172, 0x1730e5778bea1da4),
+    (0x3849674c2602319e, 0x51454b582ddf440a, 0xa25e7856cf2651eb),
+    (0x04b915ba43feb5b6, 0x42fd443059577fa2, 0x353882b109ce8f1a),
+    (0x0113b970fd34f2ce, 0x059b5e0851cf143a, 0x48f4d0884c379918),
+    (0x0170f175468fb5e6, 0x0756d8e0774761d2, 0x432193b78951fc98),
+    (0x43297fad38e373fe, 0x762514b829bf486a, 0x13f04154d69d1ae5),
+    (0x07a7137045da2a16, 0x3bdd119049372802, 0x2eedda93ffd39c79),
+    (0x04689104c2fd3b2f, 0x26955f6835af609a, 0xd887e0393c2da6e3),
+    (0x37d06bb516cb7546, 0x164d5e404f275232, 0x5f99d04f5b163969),
+    (0x1f08260d1ac2465e, 0x6b056e18759f5cca, 0x4a057a3b24d3977b),
+    (0x584023641aba6176, 0x004bd6ef09176062, 0x452031c1e4fada8e),
+    (0x025816164629b007, 0x480d39006ee762f2, 0x7555ae39f59b87bd),
+    (0x49793ebc79b3258f, 0x437540c8698f3cfa, 0x53c55f9cb49fc019),
+    (0x4fb05e1515ab73a7, 0x072d43a077075292, 0x7a8e7bfa937e89a3),
+    (0x49e95d6d4ca229bf, 0x02fe55778117f12a, 0xcf9c5d7a4986adb5),
+    (0x018310dc409b26d6, 0x1d9d5c5018f728c2, 0xd1abb290658bc778),
+    (0x1c587f1c13924fef, 0x305532286d6f295a, 0x55cb3774d13ef201),
+    (0x0101010101010101, 0x0123456789abcdef, 0xfa34ec4847b268b2),
+    (0x1f1f1f1f0e0e0e0e, 0x0123456789abcdef, 0xa790795108ea3cae),
+    (0xe0fee0fef1fef1fe, 0x0123456789abcdef, 0xc39e072d9fac631d),
+    (0x0000000000000000, 0xffffffffffffffff, 0x014933e0cdaff6e4),
+    (0xffffffffffffffff, 0x0000000000000000, 0xf21e9a77b71c49bc),
+    (0x0123456789abcdef, 0x0000000000000000, 0x245946885754369a),
+    (0xfedcba9876543210, 0xffffffffffffffff, 0x6b5c5a9c5d9e0a5a),
+];
+
+// Set-key vectors: the first n bytes of SET_KEY_KEY encrypt SET_KEY_PLAIN to
+// SET_KEY_VECTORS[n - 1].
+pub static SET_KEY_KEY: [u8; 24] = [
+    0xf0, 0xe1, 0xd2, 0xc3, 0xb4, 0xa5, 0x96, 0x87, 0x78, 0x69, 0x5a, 0x4b, 0x3c, 0x2d, 0x1e, 0x0f,
+    0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77,
+];
+pub const SET_KEY_PLAIN: u64 = 0xfedcba9876543210;
+pub static SET_KEY_VECTORS: [u64; 24] = [
+    0xf9ad597c49db005e, 0xe91d21c1d961a6d6, 0xe9c2b70a1bc65cf3, 0xbe1e639408640f05,
+    0xb39e44481bdb1e6e, 0x9457aa83b1928c0d, 0x8bb77032f960629d, 0xe87a244e2cc85e82,
+    0x15750e7a4f4ec577, 0x122ba70b3ab64ae0, 0x3a833c9affc537f6, 0x9409da87a90f6bf2,
+    0x884f80625060b8b4, 0x1f85031c19e11968, 0x79d9373a714ca34f, 0x93142887ee3be15c,
+    0x03429e838ce2d14b, 0xa4299e27469ff67b, 0xafd5aed1c1bc96a8, 0x10851c0e3858da9f,
+    0xe6f51ed79b9db21f, 0x64a6e14afd36b46f, 0x80c7d7d45a5479ad, 0x05044b62fa52d080,
+];
+
+// CBC chaining vector: 16-byte key, IV and the zero-padded "7654321 Now is the time for ".
+pub static CBC_KEY: [u8; 16] = [
+    0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0xf0, 0xe1, 0xd2, 0xc3, 0xb4, 0xa5, 0x96, 0x87,
+];
+pub const CBC_IV: u64 = 0xfedcba9876543210;
+pub static CBC_PLAIN: [u8; 32] = *b"7654321 Now is the time for \0\0\0\0";
+pub static CBC_CIPHER: [u64; 4] = [0x6b77b4d63006dee6, 0x05b156e274039793, 0x58deb9e7154616d9, 0x59f1652bd5ff92cc];
@@ -1,96 +1,445 @@
 use crate::consts::*;
 
 use crate::ecb::*;
-use ripemd::{Digest, Ripemd256};
-use std::io::Write;
+use crate::error::{Error, Result};
+use crate::options::{KeyDerivation, Options};
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
-pub struct BlowfishCTS {
+/// An expanded blowfishj CTS key: the Blowfish key schedule for the RIPEMD-256 hash of the
+/// password (or another [`KeyDerivation`]), and the feedback every message starts from.
+///
+/// The key is immutable, `Send` and `Sync`, so it can be set up once and shared between
+/// threads, for example in an `Arc`. Each message is encrypted or decrypted through its
+/// own [`CtsMessage`], which only holds the 8-byte feedback.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct BlowfishKey {
     ecb: BlowfishECB,
+    initial_feedback: [u8; BLOCK_SIZE],
+}
+
+impl BlowfishKey {
+    pub fn new(key: &[u8]) -> Result<Self> {
+        Self::new_prv(key, KeyDerivation::default(), false)
+    }
+
+    /// Creates a key with the key derivation and weak-key check of `options`.
+    pub fn with_options(key: &[u8], options: &Options) -> Result<Self> {
+        options.new_key(key)
+    }
+
+    pub(crate) fn new_prv(key: &[u8], key_derivation: KeyDerivation, reject_weak_keys: bool) -> Result<Self> {
+        let hashed_key = key_derivation.derive(key)?;
+
+        let mut ecb = BlowfishECB::new();
+        ecb.initialize(&hashed_key[..], 0, hashed_key.len())?;
+        if reject_weak_keys && ecb.is_weak_key() {
+            return Err(Error::WeakKey);
+        }
+
+        let mut initial_feedback = [0xff; BLOCK_SIZE];
+        ecb.encrypt_block(&mut initial_feedback);
+        Ok(Self { ecb, initial_feedback })
+    }
+
+    pub fn encrypt_block(&self, block: &mut [u8; BLOCK_SIZE]) {
+        self.ecb.encrypt_block(block);
+    }
+
+    pub fn decrypt_block(&self, block: &mut [u8; BLOCK_SIZE]) {
+        self.ecb.decrypt_block(block);
+    }
+
+    /// Starts a message with blowfishj's all-ones IV.
+    pub fn message(&self) -> CtsMessage<'_> {
+        CtsMessage {
+            ecb: &self.ecb,
+            feedback: self.initial_feedback,
+        }
+    }
+
+    /// Starts a message with the given IV.
+    pub fn message_with_iv(&self, iv: &[u8; BLOCK_SIZE]) -> CtsMessage<'_> {
+        let mut feedback = *iv;
+        self.ecb.encrypt_block(&mut feedback);
+        CtsMessage { ecb: &self.ecb, feedback }
+    }
+
+    /// Encrypts a whole message, exactly like [`encrypt_bytes`](crate::encrypt_bytes).
+    pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut encrypted_buf = vec![0u8; data.len()];
+        self.message().encrypt(data, 0, &mut encrypted_buf, 0, data.len())?;
+        Ok(encrypted_buf)
+    }
+
+    /// Decrypts a whole message, exactly like [`decrypt_bytes`](crate::decrypt_bytes).
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut decrypted_buf = vec![0u8; data.len()];
+        self.message().decrypt(data, 0, &mut decrypted_buf, 0, data.len())?;
+        Ok(decrypted_buf)
+    }
+}
+
+/// The state of one CTS message under a shared [`BlowfishKey`].
+///
+/// A message can be fed in several `encrypt` or `decrypt` calls, but only the last one may
+/// end in a partial block.
+#[derive(Zeroize, ZeroizeOnDrop)]
+pub struct CtsMessage<'a> {
+    #[zeroize(skip)]
+    ecb: &'a BlowfishECB,
     feedback: [u8; BLOCK_SIZE],
 }
 
-impl BlowfishCTS {
-    pub(crate) fn new() -> Self {
+impl CtsMessage<'_> {
+    pub fn encrypt(&mut self, inbuf: &[u8], inpos: usize, outbuf: &mut [u8], outpos: usize, len: usize) -> Result<usize> {
+        check_bounds(inbuf, inpos, len)?;
+        check_bounds(outbuf, outpos, len)?;
+
+        let nleft = len % BLOCK_SIZE;
+        let mut buf = [0u8; BLOCK_SIZE];
+
+        for offset in (0..len - nleft).step_by(BLOCK_SIZE) {
+            xor_buffers(&self.feedback, BLOCK_SIZE, inbuf, inpos + offset, &mut buf, 0);
+            self.ecb.encrypt(&buf, 0, outbuf, outpos + offset, BLOCK_SIZE)?;
+            xor_in_place(&mut self.feedback, &outbuf[outpos + offset..outpos + offset + BLOCK_SIZE]);
+        }
+
+        if nleft > 0 {
+            self.encrypt_tail(&inbuf[inpos + len - nleft..inpos + len], &mut outbuf[outpos + len - nleft..outpos + len]);
+        }
+
+        Ok(len)
+    }
+
+    pub fn decrypt(&mut self, inbuf: &[u8], inpos: usize, outbuf: &mut [u8], outpos: usize, len: usize) -> Result<usize> {
+        check_bounds(inbuf, inpos, len)?;
+        check_bounds(outbuf, outpos, len)?;
+
+        let nleft = len % BLOCK_SIZE;
+        let mut buf = [0u8; BLOCK_SIZE];
+
+        for offset in (0..len - nleft).step_by(BLOCK_SIZE) {
+            self.ecb.decrypt(inbuf, inpos + offset, &mut buf, 0, BLOCK_SIZE)?;
+            xor_buffers(&self.feedback, BLOCK_SIZE, &buf, 0, outbuf, outpos + offset);
+            xor_in_place(&mut self.feedback, &inbuf[inpos + offset..inpos + offset + BLOCK_SIZE]);
+        }
+
+        if nleft > 0 {
+            self.encrypt_tail(&inbuf[inpos + len - nleft..inpos + len], &mut outbuf[outpos + len - nleft..outpos + len]);
+        }
+
+        Ok(len)
+    }
+
+    // The trailing partial block is XORed with the encrypted feedback, which is the same
+    // operation in both directions.
+    fn encrypt_tail(&mut self, inbuf: &[u8], outbuf: &mut [u8]) {
+        let mut buf = self.feedback;
+        self.ecb.encrypt_block(&mut buf);
+        xor_buffers(&buf, inbuf.len(), inbuf, 0, outbuf, 0);
+        xor_in_place(&mut self.feedback, &buf);
+    }
+}
+
+/// Blowfish in blowfishj's CTS mode.
+///
+/// The key schedule and the initial feedback are fixed by [`initialize`](Self::initialize)
+/// and [`set_iv`](Self::set_iv); `encrypt` and `decrypt` only advance the feedback. Use
+/// [`reset`](Self::reset) or a clone of a freshly initialized instance to start the next
+/// message without setting up the key again.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct BlowfishCTS {
+    key: BlowfishKey,
+    initial_feedback: [u8; BLOCK_SIZE],
+    feedback: [u8; BLOCK_SIZE],
+    #[zeroize(skip)]
+    key_derivation: KeyDerivation,
+    reject_weak_keys: bool,
+}
+
+impl From<BlowfishKey> for BlowfishCTS {
+    fn from(key: BlowfishKey) -> Self {
         Self {
-            ecb: BlowfishECB::new(),
-            feedback: [0xff; BLOCK_SIZE],
+            initial_feedback: key.initial_feedback,
+            feedback: key.initial_feedback,
+            key,
+            key_derivation: KeyDerivation::default(),
+            reject_weak_keys: false,
         }
     }
 }
 
 impl BlowfishCTS {
-    pub fn initialize(&mut self, key: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
-        self.feedback = [0xff; BLOCK_SIZE];
+    /// Creates an instance keyed with the RIPEMD-256 hash of `key`, as blowfishj does.
+    pub fn with_key(key: &[u8]) -> Result<Self> {
+        Self::with_options(key, &Options::default())
+    }
+
+    /// Creates an instance keyed with the key derivation and weak-key check of `options`,
+    /// which later calls to [`initialize`](Self::initialize) keep using.
+    pub fn with_options(key: &[u8], options: &Options) -> Result<Self> {
+        let mut cts = Self::from(BlowfishKey::with_options(key, options)?);
+        cts.key_derivation = options.key_derivation;
+        cts.reject_weak_keys = options.reject_weak_keys;
+        Ok(cts)
+    }
+}
+
+impl BlowfishCTS {
+    pub fn initialize(&mut self, key: &[u8]) -> Result<()> {
+        self.key = BlowfishKey::new_prv(key, self.key_derivation, self.reject_weak_keys)?;
+        self.initial_feedback = self.key.initial_feedback;
+        self.reset();
+        Ok(())
+    }
 
-        let mut hasher = Ripemd256::new();
-        hasher.write_all(key)?;
-        let mut hashed_key = hasher.finalize();
+    /// Selects how [`initialize`](Self::initialize) turns the password into the Blowfish key.
+    /// RIPEMD-256 by default, as in blowfishj. Only the next `initialize` is affected; use
+    /// [`with_options`](Self::with_options) to set up the key only once.
+    pub fn set_key_derivation(&mut self, key_derivation: KeyDerivation) {
+        self.key_derivation = key_derivation;
+    }
 
-        self.ecb = BlowfishECB::new();
-        self.ecb.initialize(&hashed_key, 0, hashed_key.len());
-        self.ecb.encrypt(&self.feedback.clone(), 0, &mut self.feedback, 0, BLOCK_SIZE);
+    /// Makes [`initialize`](Self::initialize) fail with [`Error::WeakKey`] if the hashed key
+    /// expands to S-boxes with duplicate entries. Off by default, as in blowfishj.
+    pub fn set_reject_weak_keys(&mut self, reject: bool) {
+        self.reject_weak_keys = reject;
+    }
 
-        let backup_feedback = self.feedback.clone();
-        let hashed_key_len = hashed_key.len();
-        self.encrypt(&hashed_key.clone(), 0, &mut hashed_key, 0, hashed_key_len);
-        self.feedback.copy_from_slice(&backup_feedback);
+    /// Restarts the feedback from `iv`. [`initialize`](Self::initialize) uses an all-ones
+    /// IV, which is what blowfishj does.
+    pub fn set_iv(&mut self, iv: &[u8; BLOCK_SIZE]) {
+        self.initial_feedback = *iv;
+        self.key.encrypt_block(&mut self.initial_feedback);
+        self.feedback = self.initial_feedback;
+    }
 
-        Ok(())
+    /// Restarts the feedback from the last IV, so that the next message is encrypted as if
+    /// this instance had just been initialized.
+    pub fn reset(&mut self) {
+        self.feedback = self.initial_feedback;
     }
 
-    pub fn encrypt(&mut self, inbuf: &[u8], inpos: usize, outbuf: &mut [u8], outpos: usize, len: usize) -> usize {
-        let mut buf = [0u8; BLOCK_SIZE];
+    pub fn encrypt(&mut self, inbuf: &[u8], inpos: usize, outbuf: &mut [u8], outpos: usize, len: usize) -> Result<usize> {
+        let mut message = CtsMessage { ecb: &self.key.ecb, feedback: self.feedback };
+        message.encrypt(inbuf, inpos, outbuf, outpos, len)?;
+        self.feedback = message.feedback;
+        Ok(len)
+    }
+
+    pub fn decrypt(&mut self, inbuf: &[u8], inpos: usize, outbuf: &mut [u8], outpos: usize, len: usize) -> Result<usize> {
+        let mut message = CtsMessage { ecb: &self.key.ecb, feedback: self.feedback };
+        message.decrypt(inbuf, inpos, outbuf, outpos, len)?;
+        self.feedback = message.feedback;
+        Ok(len)
+    }
+}
+
+pub(crate) fn random_iv() -> Result<[u8; BLOCK_SIZE]> {
+    let mut iv = [0u8; BLOCK_SIZE];
+    getrandom::fill(&mut iv).map_err(|_| Error::RandomUnavailable)?;
+    Ok(iv)
+}
+
+pub(crate) fn check_bounds(buf: &[u8], pos: usize, len: usize) -> Result<()> {
+    let needed = pos.saturating_add(len);
+    if needed > buf.len() {
+        return Err(Error::BufferTooSmall { needed, available: buf.len() });
+    }
+    Ok(())
+}
+
+fn xor_buffers(data: &[u8], len: usize, src: &[u8], src_offset: usize, dst: &mut [u8], dst_offset: usize) {
+    for i in 0..len {
+        dst[i + dst_offset] = src[i + src_offset] ^ data[i];
+    }
+}
+
+fn xor_in_place(dst: &mut [u8; BLOCK_SIZE], src: &[u8]) {
+    for (d, s) in dst.iter_mut().zip(src) {
+        *d ^= s;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"foobar";
+    // RIPEMD-256 of this password expands to S-boxes with a duplicate entry.
+    const WEAK_KEY: &[u8] = b"weak31364";
+    const TEXT: &[u8] = b"How I wish I could recollect PI easily using one trick?";
+
+    fn cts() -> BlowfishCTS {
+        BlowfishCTS::with_key(KEY).unwrap()
+    }
 
-        for offset in (0..len).step_by(BLOCK_SIZE) {
-            if len - offset >= BLOCK_SIZE {
-                xor_buffers(&self.feedback, BLOCK_SIZE, inbuf, inpos + offset, &mut buf, 0);
-                self.ecb.encrypt(&buf, 0, outbuf, outpos + offset, BLOCK_SIZE);
-                xor_buffers(&self.feedback.clone(), BLOCK_SIZE, outbuf, outpos + offset, &mut self.feedback, 0);
+    fn encrypt_at_zero(data: &[u8]) -> Vec<u8> {
+        let mut out = vec![0u8; data.len()];
+        cts().encrypt(data, 0, &mut out, 0, data.len()).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_offsets() {
+        for len in 0..=3 * BLOCK_SIZE {
+            let plain = &TEXT[..len];
+            let expected = encrypt_at_zero(plain);
+
+            for (inpos, outpos) in [(0, 5), (3, 0), (7, 11), (9, 9)] {
+                let mut inbuf = vec![0xaau8; inpos + len + 4];
+                inbuf[inpos..inpos + len].copy_from_slice(plain);
+                let mut outbuf = vec![0x55u8; outpos + len + 4];
+
+                assert_eq!(cts().encrypt(&inbuf, inpos, &mut outbuf, outpos, len), Ok(len));
+                assert_eq!(&outbuf[outpos..outpos + len], &expected[..], "len {} inpos {} outpos {}", len, inpos, outpos);
+                assert!(outbuf[..outpos].iter().all(|&b| b == 0x55));
+                assert!(outbuf[outpos + len..].iter().all(|&b| b == 0x55));
+
+                let mut decrypted = vec![0x55u8; inpos + len + 4];
+                assert_eq!(cts().decrypt(&outbuf, outpos, &mut decrypted, inpos, len), Ok(len));
+                assert_eq!(&decrypted[inpos..inpos + len], plain, "len {} inpos {} outpos {}", len, inpos, outpos);
+                assert!(decrypted[..inpos].iter().all(|&b| b == 0x55));
+                assert!(decrypted[inpos + len..].iter().all(|&b| b == 0x55));
+            }
+        }
+    }
+
+    #[test]
+    fn test_tail_lengths() {
+        for blocks in 0..3 {
+            for tail in 1..BLOCK_SIZE {
+                let len = blocks * BLOCK_SIZE + tail;
+                let encrypted = encrypt_at_zero(&TEXT[..len]);
+                assert_eq!(&encrypted[..len - tail], &encrypt_at_zero(&TEXT[..len - tail])[..]);
+
+                let mut decrypted = vec![0u8; len];
+                cts().decrypt(&encrypted, 0, &mut decrypted, 0, len).unwrap();
+                assert_eq!(&decrypted[..], &TEXT[..len], "len {}", len);
             }
         }
+    }
 
-        if len % BLOCK_SIZE > 0 {
-            let nleft = len % BLOCK_SIZE;
-            let offset = len - nleft;
+    #[test]
+    fn test_reset_and_clone() {
+        let expected = encrypt_at_zero(TEXT);
+        let mut cts = cts();
+        let fresh = cts.clone();
 
-            buf.copy_from_slice(&self.feedback);
-            self.ecb.encrypt(&buf.clone(), 0, &mut buf, 0, BLOCK_SIZE);
-            xor_buffers(&buf, nleft, inbuf, offset, outbuf, offset);
-            xor_buffers(&self.feedback.clone(), BLOCK_SIZE, &buf, 0, &mut self.feedback, 0);
+        for _ in 0..2 {
+            let mut out = vec![0u8; TEXT.len()];
+            cts.encrypt(TEXT, 0, &mut out, 0, TEXT.len()).unwrap();
+            assert_eq!(out, expected);
+            cts.reset();
         }
 
-        len
+        let mut out = vec![0u8; TEXT.len()];
+        fresh.clone().encrypt(TEXT, 0, &mut out, 0, TEXT.len()).unwrap();
+        assert_eq!(out, expected);
+
+        cts.set_iv(&[1; BLOCK_SIZE]);
+        let mut first = vec![0u8; TEXT.len()];
+        cts.encrypt(TEXT, 0, &mut first, 0, TEXT.len()).unwrap();
+        cts.reset();
+        let mut second = vec![0u8; TEXT.len()];
+        cts.encrypt(TEXT, 0, &mut second, 0, TEXT.len()).unwrap();
+        assert_eq!(first, second);
+        assert_ne!(first, expected);
     }
 
-    pub fn decrypt(&mut self, inbuf: &[u8], inpos: usize, outbuf: &mut [u8], outpos: usize, len: usize) -> usize {
-        let mut buf = [0u8; BLOCK_SIZE];
-        outbuf.copy_from_slice(inbuf);
-
-        for offset in (0..len).step_by(BLOCK_SIZE) {
-            if len - offset >= BLOCK_SIZE {
-                xor_buffers(&self.feedback.clone(), BLOCK_SIZE, &outbuf.to_owned(), outpos + offset, &mut buf, 0);
-                self.ecb.decrypt(&inbuf, inpos + offset, outbuf, outpos + offset, BLOCK_SIZE);
-                xor_buffers(&self.feedback.clone(), BLOCK_SIZE, &outbuf.to_owned(), outpos + offset, outbuf, outpos + offset);
-                self.feedback.copy_from_slice(&buf);
-            }
+    #[test]
+    fn test_shared_key() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<BlowfishKey>();
+
+        let key = std::sync::Arc::new(BlowfishKey::new(KEY).unwrap());
+        let expected = encrypt_at_zero(TEXT);
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let key = key.clone();
+                std::thread::spawn(move || key.encrypt(TEXT).unwrap())
+            })
+            .collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), expected);
         }
+        assert_eq!(key.decrypt(&expected).unwrap(), TEXT);
+
+        let mut cts = BlowfishCTS::from((*key).clone());
+        let mut out = vec![0u8; TEXT.len()];
+        cts.encrypt(TEXT, 0, &mut out, 0, TEXT.len()).unwrap();
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_message_in_pieces() {
+        let key = BlowfishKey::new(KEY).unwrap();
+        let iv = [7u8; BLOCK_SIZE];
+        let mut cts = cts();
+        cts.set_iv(&iv);
+        let mut expected = vec![0u8; TEXT.len()];
+        cts.encrypt(TEXT, 0, &mut expected, 0, TEXT.len()).unwrap();
+
+        let mut message = key.message_with_iv(&iv);
+        let mut out = vec![0u8; TEXT.len()];
+        message.encrypt(TEXT, 0, &mut out, 0, 16).unwrap();
+        message.encrypt(TEXT, 16, &mut out, 16, TEXT.len() - 16).unwrap();
+        assert_eq!(out, expected);
+
+        let mut message = key.message_with_iv(&iv);
+        let mut decrypted = vec![0u8; TEXT.len()];
+        message.decrypt(&out, 0, &mut decrypted, 0, 24).unwrap();
+        message.decrypt(&out, 24, &mut decrypted, 24, TEXT.len() - 24).unwrap();
+        assert_eq!(decrypted, TEXT);
+    }
 
-        if len % BLOCK_SIZE > 0 {
-            let nleft = len % BLOCK_SIZE;
-            let offset = len - nleft;
+    #[test]
+    fn test_reject_weak_keys() {
+        let mut cts = cts();
+        assert_eq!(cts.initialize(WEAK_KEY), Ok(()));
 
-            buf.copy_from_slice(&self.feedback);
-            self.ecb.encrypt(&buf.clone(), 0, &mut buf, 0, BLOCK_SIZE);
-            xor_buffers(&buf, nleft, inbuf, offset, outbuf, offset);
-            xor_buffers(&self.feedback.clone(), BLOCK_SIZE, &buf, 0, &mut self.feedback, 0);
+        cts.set_reject_weak_keys(true);
+        assert_eq!(cts.initialize(WEAK_KEY), Err(Error::WeakKey));
+        assert_eq!(cts.initialize(KEY), Ok(()));
+    }
+
+    #[test]
+    fn test_with_options() {
+        for key_derivation in [KeyDerivation::Sha1, KeyDerivation::Sha256, KeyDerivation::Raw] {
+            let options = Options::new().key_derivation(key_derivation);
+            let expected = crate::encrypt_bytes_with(&options, KEY, TEXT).unwrap();
+            assert_ne!(expected, encrypt_at_zero(TEXT));
+            assert_eq!(BlowfishKey::with_options(KEY, &options).unwrap().encrypt(TEXT).unwrap(), expected);
+
+            let mut cts = BlowfishCTS::with_options(KEY, &options).unwrap();
+            let mut out = vec![0u8; TEXT.len()];
+            cts.encrypt(TEXT, 0, &mut out, 0, TEXT.len()).unwrap();
+            assert_eq!(out, expected);
+
+            cts.initialize(KEY).unwrap();
+            cts.encrypt(TEXT, 0, &mut out, 0, TEXT.len()).unwrap();
+            assert_eq!(out, expected);
         }
 
-        len
+        let options = Options::new().reject_weak_keys(true);
+        assert_eq!(BlowfishKey::with_options(WEAK_KEY, &options).err(), Some(Error::WeakKey));
+        assert_eq!(BlowfishCTS::with_options(WEAK_KEY, &options).err(), Some(Error::WeakKey));
     }
-}
 
-fn xor_buffers(data: &[u8], len: usize, src: &[u8], src_offset: usize, dst: &mut [u8], dst_offset: usize) {
-    for i in 0..len {
-        dst[i + dst_offset] = (src[i + src_offset] ^ data[i]) & 0xff;
+    #[test]
+    fn test_short_buffers() {
+        let mut outbuf = [0u8; 16];
+        assert_eq!(
+            cts().encrypt(&TEXT[..10], 4, &mut outbuf, 0, 8),
+            Err(Error::BufferTooSmall { needed: 12, available: 10 })
+        );
+        assert_eq!(
+            cts().decrypt(&TEXT[..16], 0, &mut outbuf, 9, 8),
+            Err(Error::BufferTooSmall { needed: 17, available: 16 })
+        );
+        assert_eq!(
+            cts().encrypt(TEXT, usize::MAX, &mut outbuf, 0, 1),
+            Err(Error::BufferTooSmall { needed: usize::MAX, available: TEXT.len() })
+        );
     }
 }
@@ -0,0 +1,131 @@
+use crate::cbc::BlowfishCBC;
+use crate::consts::*;
+use crate::cts::random_iv;
+use crate::error::{Error, Result};
+use sha1::{Digest, Sha1};
+use zeroize::Zeroizing;
+
+/// A port of blowfishj's `BlowfishEasy`, for strings written by `encryptString`.
+///
+/// The key is the SHA-1 hash of the password as UTF-16BE. Each string is turned into
+/// UTF-16BE, padded to the next full block with bytes holding the pad length (1 to 8),
+/// and encrypted with [`BlowfishCBC`] under a random IV. The result is lowercase hex of
+/// the IV followed by the ciphertext.
+pub struct BlowfishEasy {
+    cbc: BlowfishCBC,
+}
+
+impl BlowfishEasy {
+    pub fn new(password: &str) -> Result<Self> {
+        let mut sha1 = Sha1::new();
+        for c in password.encode_utf16() {
+            sha1.update(c.to_be_bytes());
+        }
+        let hash = Zeroizing::new(<[u8; 20]>::from(sha1.finalize()));
+        Ok(Self { cbc: BlowfishCBC::with_key(&hash[..])? })
+    }
+
+    /// Encrypts `plain` with a random IV (`encryptString`).
+    pub fn encrypt_string(&mut self, plain: &str) -> Result<String> {
+        let iv = u64::from_be_bytes(random_iv()?);
+        self.encrypt_string_with_iv(plain, iv)
+    }
+
+    /// Encrypts `plain` with the given IV. Strings encrypted under the same IV show
+    /// whether they start with the same blocks of text.
+    pub fn encrypt_string_with_iv(&mut self, plain: &str, iv: u64) -> Result<String> {
+        let mut buf = Zeroizing::new(Vec::with_capacity(plain.len() * 2 + BLOCK_SIZE));
+        for c in plain.encode_utf16() {
+            buf.extend_from_slice(&c.to_be_bytes());
+        }
+        let pad = BLOCK_SIZE - buf.len() % BLOCK_SIZE;
+        let len = buf.len() + pad;
+        buf.resize(len, pad as u8);
+
+        let mut encrypted_buf = vec![0u8; BLOCK_SIZE + len];
+        encrypted_buf[..BLOCK_SIZE].copy_from_slice(&iv.to_be_bytes());
+        self.cbc.set_iv(iv);
+        self.cbc.encrypt(&buf, 0, &mut encrypted_buf, BLOCK_SIZE, len)?;
+        Ok(hex::encode(encrypted_buf))
+    }
+
+    /// Decrypts a string from `encryptString` (`decryptString`).
+    ///
+    /// As in blowfishj, trailing hex digits that do not make up a whole block are ignored,
+    /// and a pad byte greater than 8 is taken as no padding. Where blowfishj returns `null`,
+    /// this returns [`Error::Truncated`] or a hex error, and decrypted UTF-16 with unpaired
+    /// surrogates, which a Rust string cannot hold, gives [`Error::InvalidText`].
+    pub fn decrypt_string(&mut self, src: &str) -> Result<String> {
+        let len = (src.len() / 2) & !(BLOCK_SIZE - 1);
+        if len < BLOCK_SIZE {
+            return Err(Error::Truncated);
+        }
+        let buf = hex::decode(&src.as_bytes()[..len * 2])?;
+
+        let (iv, encrypted) = buf.split_at(BLOCK_SIZE);
+        self.cbc.set_iv_bytes(iv.try_into().unwrap());
+        let mut decrypted_buf = Zeroizing::new(vec![0u8; encrypted.len()]);
+        self.cbc.decrypt(encrypted, 0, &mut decrypted_buf, 0, encrypted.len())?;
+
+        let mut len = decrypted_buf.len();
+        if let Some(&pad) = decrypted_buf.last() {
+            if pad as usize <= BLOCK_SIZE {
+                len = len.saturating_sub(pad as usize);
+            }
+        }
+        let chars: Zeroizing<Vec<u16>> = Zeroizing::new(
+            decrypted_buf[..len]
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect(),
+        );
+        String::from_utf16(&chars).map_err(|_| Error::InvalidText { charset: "UTF-16BE" })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IV: u64 = 0x0123456789abcdef;
+
+    // Produced by the BlowfishEasy algorithm in Java with the JCE's SHA-1 and
+    // Blowfish/CBC/NoPadding.
+    const FIXTURES: &[(&str, &str, &str)] = &[
+        ("foobar", "How I wish I could recollect PI easily using one trick?", "0123456789abcdef6b7c97c151a0e27c71e74f124fa547f56de21e32860949730d11ccaa780ab4cb9cdaa446cf50cd3093cfa350e6cd363cb8f86acbfb8da91ca7f03e0292d335866a34b76bd9966496d2d2284479b6061da94a1d3baebafd496b372ffebc85b3fba9edd37561c402ef8a166631da38b552"),
+        ("密碼", "一二三四 one two 3 4", "0123456789abcdef93abc941fd0a4f88314bac5fa430313b3c17e4e1b27fbe557a6f7831bc51c6a6e5e6faf245579726"),
+        ("foobar", "", "0123456789abcdeffe8aad8853df7a41"),
+        ("pw", "abcd", "0123456789abcdef952ec8a454bffaba9a279a15885edfa2"),
+        ("pw", "😀 emoji", "0123456789abcdefcae029f63114fdd45b8ad6b3f1269ba34776e835b8087b3d"),
+    ];
+
+    #[test]
+    fn test_fixtures() {
+        for &(password, plain, encrypted) in FIXTURES {
+            let mut easy = BlowfishEasy::new(password).unwrap();
+            assert_eq!(easy.encrypt_string_with_iv(plain, IV).as_deref(), Ok(encrypted));
+            assert_eq!(easy.decrypt_string(encrypted).as_deref(), Ok(plain));
+            assert_eq!(easy.decrypt_string(&encrypted.to_uppercase()).as_deref(), Ok(plain));
+        }
+    }
+
+    #[test]
+    fn test_random_iv() {
+        let mut easy = BlowfishEasy::new("foobar").unwrap();
+        let first = easy.encrypt_string("text").unwrap();
+        let second = easy.encrypt_string("text").unwrap();
+        assert_ne!(first, second);
+        assert_eq!(easy.decrypt_string(&first).as_deref(), Ok("text"));
+        assert_eq!(easy.decrypt_string(&second).as_deref(), Ok("text"));
+    }
+
+    #[test]
+    fn test_decrypt_leniency() {
+        let mut easy = BlowfishEasy::new("pw").unwrap();
+        let (_, plain, encrypted) = FIXTURES[3];
+        assert_eq!(easy.decrypt_string(&format!("{}abc", encrypted)).as_deref(), Ok(plain));
+        assert_eq!(easy.decrypt_string(&encrypted[..16]).as_deref(), Ok(""));
+        assert_eq!(easy.decrypt_string(&encrypted[..15]), Err(Error::Truncated));
+        assert_eq!(easy.decrypt_string(""), Err(Error::Truncated));
+    }
+}
@@ -1,39 +1,60 @@
 use crate::consts::*;
-
+use crate::cts::check_bounds;
+use crate::error::{Error, Result};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// The raw Blowfish block cipher, keyed directly with the given bytes.
+///
+/// The offset-based `encrypt`/`decrypt` mirror blowfishj, but fail with
+/// [`Error::NotBlockAligned`] instead of ignoring trailing bytes, and with
+/// [`Error::BufferTooSmall`] instead of panicking. The block and slice helpers are easier
+/// to use.
+///
+/// The key schedule is zeroized when the cipher is dropped.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
 pub struct BlowfishECB {
     pbox: [i32; PBOX_ENTRIES],
     sbox1: [i32; SBOX_ENTRIES],
     sbox2: [i32; SBOX_ENTRIES],
     sbox3: [i32; SBOX_ENTRIES],
     sbox4: [i32; SBOX_ENTRIES],
-    block_buf: [u8; BLOCK_SIZE],
     weak_key: i32,
 }
 
 impl BlowfishECB {
-    pub fn new() -> Self {
+    /// Creates a cipher with an all-zero key schedule, which does not encrypt at all; only
+    /// for callers that `initialize` it right away.
+    pub(crate) fn new() -> Self {
         Self {
             pbox: [0; PBOX_ENTRIES],
             sbox1: [0; SBOX_ENTRIES],
             sbox2: [0; SBOX_ENTRIES],
             sbox3: [0; SBOX_ENTRIES],
             sbox4: [0; SBOX_ENTRIES],
-            block_buf: [0; BLOCK_SIZE],
             weak_key: -1,
         }
     }
 
-    pub fn initialize(&mut self, key: &[u8], mut ofs: usize, len: usize) {
+    /// Creates a cipher keyed with `key`, which must not be empty. Blowfish uses at most
+    /// 72 bytes of key; longer keys have no further effect.
+    pub fn with_key(key: &[u8]) -> Result<Self> {
+        let mut ecb = Self::new();
+        ecb.initialize(key, 0, key.len())?;
+        Ok(ecb)
+    }
+
+    pub fn initialize(&mut self, key: &[u8], mut ofs: usize, len: usize) -> Result<()> {
+        if len == 0 {
+            return Err(Error::EmptyKey);
+        }
+        check_bounds(key, ofs, len)?;
+
         copy_array_uint32_to_int32(&PBOX_INIT, &mut self.pbox);
         copy_array_uint32_to_int32(&SBOX1_INIT, &mut self.sbox1);
         copy_array_uint32_to_int32(&SBOX2_INIT, &mut self.sbox2);
         copy_array_uint32_to_int32(&SBOX3_INIT, &mut self.sbox3);
         copy_array_uint32_to_int32(&SBOX4_INIT, &mut self.sbox4);
 
-        if len == 0 {
-            return;
-        }
-
         let mut build = 0;
         let ofs_bak = ofs;
         let end = ofs + len;
@@ -49,61 +70,138 @@ impl BlowfishECB {
             self.pbox[i] ^= build;
         }
 
-        self.block_buf.fill(0);
+        let mut block = [0u8; BLOCK_SIZE];
 
         for i in (0..PBOX_ENTRIES).step_by(2) {
-            let inbuf = self.block_buf.clone();
-            let mut outbuf = self.block_buf.clone();
-            self.encrypt_prv(&inbuf, 0, &mut outbuf, 0, BLOCK_SIZE);
-            self.block_buf.copy_from_slice(&outbuf);
-            self.pbox[i] = byte_array_to_int(&self.block_buf, 0);
-            self.pbox[i + 1] = byte_array_to_int(&self.block_buf, 4);
+            self.encrypt_block(&mut block);
+            self.pbox[i] = byte_array_to_int(&block, 0);
+            self.pbox[i + 1] = byte_array_to_int(&block, 4);
         }
 
         for i in (0..SBOX_ENTRIES).step_by(2) {
-            let inbuf = self.block_buf.clone();
-            let mut outbuf = self.block_buf.clone();
-            self.encrypt_prv(&inbuf, 0, &mut outbuf, 0, BLOCK_SIZE);
-            self.block_buf.copy_from_slice(&outbuf);
-            self.sbox1[i] = byte_array_to_int(&self.block_buf, 0);
-            self.sbox1[i + 1] = byte_array_to_int(&self.block_buf, 4);
+            self.encrypt_block(&mut block);
+            self.sbox1[i] = byte_array_to_int(&block, 0);
+            self.sbox1[i + 1] = byte_array_to_int(&block, 4);
         }
 
         for i in (0..SBOX_ENTRIES).step_by(2) {
-            let inbuf = self.block_buf.clone();
-            let mut outbuf = self.block_buf.clone();
-            self.encrypt_prv(&inbuf, 0, &mut outbuf, 0, BLOCK_SIZE);
-            self.block_buf.copy_from_slice(&outbuf);
-            self.sbox2[i] = byte_array_to_int(&self.block_buf, 0);
-            self.sbox2[i + 1] = byte_array_to_int(&self.block_buf, 4);
+            self.encrypt_block(&mut block);
+            self.sbox2[i] = byte_array_to_int(&block, 0);
+            self.sbox2[i + 1] = byte_array_to_int(&block, 4);
         }
 
         for i in (0..SBOX_ENTRIES).step_by(2) {
-            let inbuf = self.block_buf.clone();
-            let mut outbuf = self.block_buf.clone();
-            self.encrypt_prv(&inbuf, 0, &mut outbuf, 0, BLOCK_SIZE);
-            self.block_buf.copy_from_slice(&outbuf);
-            self.sbox3[i] = byte_array_to_int(&self.block_buf, 0);
-            self.sbox3[i + 1] = byte_array_to_int(&self.block_buf, 4);
+            self.encrypt_block(&mut block);
+            self.sbox3[i] = byte_array_to_int(&block, 0);
+            self.sbox3[i + 1] = byte_array_to_int(&block, 4);
         }
 
         for i in (0..SBOX_ENTRIES).step_by(2) {
-            let inbuf = self.block_buf.clone();
-            let mut outbuf = self.block_buf.clone();
-            self.encrypt_prv(&inbuf, 0, &mut outbuf, 0, BLOCK_SIZE);
-            self.block_buf.copy_from_slice(&outbuf);
-            self.sbox4[i] = byte_array_to_int(&self.block_buf, 0);
-            self.sbox4[i + 1] = byte_array_to_int(&self.block_buf, 4);
+            self.encrypt_block(&mut block);
+            self.sbox4[i] = byte_array_to_int(&block, 0);
+            self.sbox4[i + 1] = byte_array_to_int(&block, 4);
         }
 
         self.weak_key = -1;
+
+        Ok(())
+    }
+
+    /// Runs Eric Young's variable-key, set-key and CBC chaining test vectors, like
+    /// blowfishj's `selfTest`.
+    pub fn self_test() -> Result<()> {
+        for &(key, plain, cipher) in ECB_VECTORS.iter() {
+            let ecb = Self::with_key(&key.to_be_bytes())?;
+            if ecb.encrypt_u64(plain) != cipher || ecb.decrypt_u64(cipher) != plain {
+                return Err(Error::SelfTestFailed);
+            }
+        }
+
+        for (i, &cipher) in SET_KEY_VECTORS.iter().enumerate() {
+            let ecb = Self::with_key(&SET_KEY_KEY[..=i])?;
+            if ecb.encrypt_u64(SET_KEY_PLAIN) != cipher || ecb.decrypt_u64(cipher) != SET_KEY_PLAIN {
+                return Err(Error::SelfTestFailed);
+            }
+        }
+
+        let ecb = Self::with_key(&CBC_KEY)?;
+        let mut feedback = CBC_IV;
+        for (block, &cipher) in CBC_PLAIN.chunks_exact(BLOCK_SIZE).zip(CBC_CIPHER.iter()) {
+            let plain = u64::from_be_bytes(block.try_into().unwrap());
+            feedback = ecb.encrypt_u64(plain ^ feedback);
+            if feedback != cipher {
+                return Err(Error::SelfTestFailed);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks the expanded S-boxes for duplicate entries, which makes the key weak against
+    /// Vaudenay's attack on reduced-round Blowfish. The result is cached until the next
+    /// `initialize`.
+    pub fn is_weak_key(&mut self) -> bool {
+        if self.weak_key == -1 {
+            self.weak_key = [&self.sbox1, &self.sbox2, &self.sbox3, &self.sbox4]
+                .iter()
+                .any(|sbox| has_duplicates(&sbox[..])) as i32;
+        }
+        self.weak_key == 1
+    }
+
+    pub fn encrypt(&self, inbuf: &[u8], inpos: usize, outbuf: &mut [u8], outpos: usize, len: usize) -> Result<usize> {
+        check_offsets(inbuf, inpos, outbuf, outpos, len)?;
+        Ok(self.encrypt_prv(inbuf, inpos, outbuf, outpos, len))
+    }
+
+    pub fn decrypt(&self, inbuf: &[u8], inpos: usize, outbuf: &mut [u8], outpos: usize, len: usize) -> Result<usize> {
+        check_offsets(inbuf, inpos, outbuf, outpos, len)?;
+        Ok(self.decrypt_prv(inbuf, inpos, outbuf, outpos, len))
+    }
+
+    pub fn encrypt_block(&self, block: &mut [u8; BLOCK_SIZE]) {
+        let inbuf = *block;
+        self.encrypt_prv(&inbuf, 0, block, 0, BLOCK_SIZE);
+    }
+
+    pub fn decrypt_block(&self, block: &mut [u8; BLOCK_SIZE]) {
+        let inbuf = *block;
+        self.decrypt_prv(&inbuf, 0, block, 0, BLOCK_SIZE);
+    }
+
+    /// Encrypts a block given as a big-endian 64-bit integer.
+    pub fn encrypt_u64(&self, block: u64) -> u64 {
+        let mut buf = block.to_be_bytes();
+        self.encrypt_block(&mut buf);
+        u64::from_be_bytes(buf)
+    }
+
+    /// Decrypts a block given as a big-endian 64-bit integer.
+    pub fn decrypt_u64(&self, block: u64) -> u64 {
+        let mut buf = block.to_be_bytes();
+        self.decrypt_block(&mut buf);
+        u64::from_be_bytes(buf)
     }
 
-    pub fn encrypt(&mut self, inbuf: &[u8], inpos: usize, outbuf: &mut [u8], outpos: usize, len: usize) -> usize {
-        self.encrypt_prv(inbuf, inpos, outbuf, outpos, len)
+    /// Encrypts `data` in place; its length must be a multiple of [`BLOCK_SIZE`].
+    pub fn encrypt_blocks(&self, data: &mut [u8]) -> Result<()> {
+        check_aligned(data)?;
+        for block in data.chunks_exact_mut(BLOCK_SIZE) {
+            self.encrypt_block(block.try_into().unwrap());
+        }
+        Ok(())
+    }
+
+    /// Decrypts `data` in place; its length must be a multiple of [`BLOCK_SIZE`].
+    pub fn decrypt_blocks(&self, data: &mut [u8]) -> Result<()> {
+        check_aligned(data)?;
+        for block in data.chunks_exact_mut(BLOCK_SIZE) {
+            self.decrypt_block(block.try_into().unwrap());
+        }
+        Ok(())
     }
 
-    fn encrypt_prv(&mut self, inbuf: &[u8], inpos: usize, outbuf: &mut [u8], outpos: usize, len: usize) -> usize {
+    fn encrypt_prv(&self, inbuf: &[u8], inpos: usize, outbuf: &mut [u8], outpos: usize, len: usize) -> usize {
         let len = len - (len % BLOCK_SIZE);
         let c = inpos + len;
 
@@ -187,7 +285,7 @@ impl BlowfishECB {
         len
     }
 
-    pub fn decrypt(&mut self, inbuf: &[u8], inpos: usize, outbuf: &mut [u8], outpos: usize, len: usize) -> usize {
+    fn decrypt_prv(&self, inbuf: &[u8], inpos: usize, outbuf: &mut [u8], outpos: usize, len: usize) -> usize {
         let len = len - (len % BLOCK_SIZE);
         let c = inpos + len;
 
@@ -272,6 +370,25 @@ impl BlowfishECB {
 }
 
 
+fn has_duplicates(sbox: &[i32]) -> bool {
+    (0..sbox.len()).any(|i| sbox[i + 1..].contains(&sbox[i]))
+}
+
+fn check_aligned(data: &[u8]) -> Result<()> {
+    if !data.len().is_multiple_of(BLOCK_SIZE) {
+        return Err(Error::NotBlockAligned(data.len()));
+    }
+    Ok(())
+}
+
+pub(crate) fn check_offsets(inbuf: &[u8], inpos: usize, outbuf: &[u8], outpos: usize, len: usize) -> Result<()> {
+    if !len.is_multiple_of(BLOCK_SIZE) {
+        return Err(Error::NotBlockAligned(len));
+    }
+    check_bounds(inbuf, inpos, len)?;
+    check_bounds(outbuf, outpos, len)
+}
+
 fn copy_array_uint32_to_int32(src: &[u32], dst: &mut [i32]) {
     for (i, &v) in src.iter().enumerate() {
         dst[i] = v as i32;
@@ -284,3 +401,106 @@ fn byte_array_to_int(buf: &[u8], ofs: usize) -> i32 {
         | ((buf[ofs + 2] as i32) & 0x0FF) << 8
         | (buf[ofs + 3] as i32) & 0x0FF
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_answer() {
+        let ecb = BlowfishECB::with_key(&[0; 8]).unwrap();
+        assert_eq!(ecb.encrypt_u64(0), 0x4ef997456198dd78);
+        assert_eq!(ecb.decrypt_u64(0x4ef997456198dd78), 0);
+
+        let ecb = BlowfishECB::with_key(&[0xfe, 0xdc, 0xba, 0x98, 0x76, 0x54, 0x32, 0x10]).unwrap();
+        let mut block = 0x0123456789abcdefu64.to_be_bytes();
+        ecb.encrypt_block(&mut block);
+        assert_eq!(block, 0x0aceab0fc6a0a28du64.to_be_bytes());
+        ecb.decrypt_block(&mut block);
+        assert_eq!(block, 0x0123456789abcdefu64.to_be_bytes());
+    }
+
+    #[test]
+    fn test_self_test() {
+        assert_eq!(BlowfishECB::self_test(), Ok(()));
+    }
+
+    #[test]
+    fn test_vector_round_trips() {
+        for &(key, plain, cipher) in ECB_VECTORS.iter() {
+            let ecb = BlowfishECB::with_key(&key.to_be_bytes()).unwrap();
+            let mut block = plain.to_be_bytes();
+            ecb.encrypt_block(&mut block);
+            assert_eq!(u64::from_be_bytes(block), cipher, "key {:016x}", key);
+            ecb.decrypt_block(&mut block);
+            assert_eq!(u64::from_be_bytes(block), plain, "key {:016x}", key);
+        }
+
+        for (i, &cipher) in SET_KEY_VECTORS.iter().enumerate() {
+            let ecb = BlowfishECB::with_key(&SET_KEY_KEY[..=i]).unwrap();
+            assert_eq!(ecb.encrypt_u64(SET_KEY_PLAIN), cipher, "key length {}", i + 1);
+        }
+
+        let ecb = BlowfishECB::with_key(&CBC_KEY).unwrap();
+        let mut data = CBC_PLAIN;
+        ecb.encrypt_blocks(&mut data).unwrap();
+        ecb.decrypt_blocks(&mut data).unwrap();
+        assert_eq!(data, CBC_PLAIN);
+    }
+
+    #[test]
+    fn test_blocks_match_offset_api() {
+        let ecb = BlowfishECB::with_key(b"foobar").unwrap();
+        let plain: Vec<u8> = (0..64).collect();
+
+        let mut expected = vec![0u8; plain.len()];
+        assert_eq!(ecb.encrypt(&plain, 0, &mut expected, 0, plain.len()), Ok(64));
+
+        let mut data = plain.clone();
+        ecb.encrypt_blocks(&mut data).unwrap();
+        assert_eq!(data, expected);
+        ecb.decrypt_blocks(&mut data).unwrap();
+        assert_eq!(data, plain);
+    }
+
+    #[test]
+    fn test_weak_key() {
+        let mut ecb = BlowfishECB::with_key(&[0x00, 0x00, 0x22, 0x97]).unwrap();
+        assert_eq!(ecb.weak_key, -1);
+        assert!(ecb.is_weak_key());
+        assert_eq!(ecb.weak_key, 1);
+
+        ecb.initialize(&[0x00, 0x00, 0x22, 0x96], 0, 4).unwrap();
+        assert_eq!(ecb.weak_key, -1);
+        assert!(!ecb.is_weak_key());
+        assert_eq!(ecb.weak_key, 0);
+    }
+
+    #[test]
+    fn test_zeroize() {
+        let mut ecb = BlowfishECB::with_key(b"foobar").unwrap();
+        ecb.zeroize();
+        assert!(ecb.pbox.iter().all(|&v| v == 0));
+        for sbox in [&ecb.sbox1, &ecb.sbox2, &ecb.sbox3, &ecb.sbox4] {
+            assert!(sbox.iter().all(|&v| v == 0));
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let ecb = BlowfishECB::with_key(b"foobar").unwrap();
+        assert_eq!(ecb.encrypt_blocks(&mut [0; 12]), Err(Error::NotBlockAligned(12)));
+        assert_eq!(ecb.decrypt_blocks(&mut [0; 7]), Err(Error::NotBlockAligned(7)));
+        assert!(ecb.encrypt_blocks(&mut []).is_ok());
+        assert_eq!(ecb.encrypt(&[0; 16], 0, &mut [0; 16], 0, 12), Err(Error::NotBlockAligned(12)));
+        assert_eq!(ecb.decrypt(&[0; 16], 0, &mut [0; 16], 0, 7), Err(Error::NotBlockAligned(7)));
+        assert_eq!(ecb.encrypt(&[0; 16], 12, &mut [0; 16], 0, 8), Err(Error::BufferTooSmall { needed: 20, available: 16 }));
+        assert_eq!(ecb.decrypt(&[0; 16], 0, &mut [0; 16], 9, 8), Err(Error::BufferTooSmall { needed: 17, available: 16 }));
+        assert!(matches!(BlowfishECB::with_key(b""), Err(Error::EmptyKey)));
+        let mut ecb = BlowfishECB::new();
+        assert_eq!(ecb.initialize(&[1, 2], 5, 3), Err(Error::BufferTooSmall { needed: 8, available: 2 }));
+        assert_eq!(ecb.initialize(&[1, 2], 1, 2), Err(Error::BufferTooSmall { needed: 3, available: 2 }));
+        assert_eq!(ecb.initialize(&[1, 2], usize::MAX, 2), Err(Error::BufferTooSmall { needed: usize::MAX, available: 2 }));
+        assert!(ecb.initialize(&[1, 2, 3], 1, 2).is_ok());
+    }
+}
@@ -0,0 +1,134 @@
+use crate::error::{Error, Result};
+use crate::options::Options;
+use base64::alphabet;
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig, STANDARD, URL_SAFE_NO_PAD};
+use base64::engine::DecodePaddingMode;
+use base64::Engine;
+
+// Decoders that accept input with or without `=` padding.
+const BASE64_LENIENT: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+const BASE64_URL_LENIENT: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// How ciphertext is turned into text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// Uppercase hex, as written by blowfishj. Decoding accepts either case.
+    #[default]
+    UpperHex,
+    /// Lowercase hex. Decoding accepts either case.
+    LowerHex,
+    /// Standard Base64 with `=` padding. Decoding accepts missing padding.
+    Base64,
+    /// URL-safe Base64 without padding. Decoding accepts `=` padding.
+    Base64Url,
+    /// No encoding at all. Only usable with [`Encoding::encode`] and [`Encoding::decode`],
+    /// since the string functions cannot hold arbitrary bytes; they fail with
+    /// [`Error::NotText`].
+    Raw,
+}
+
+impl Encoding {
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Encoding::UpperHex => hex::encode_upper(data).into_bytes(),
+            Encoding::LowerHex => hex::encode(data).into_bytes(),
+            Encoding::Base64 => STANDARD.encode(data).into_bytes(),
+            Encoding::Base64Url => URL_SAFE_NO_PAD.encode(data).into_bytes(),
+            Encoding::Raw => data.to_vec(),
+        }
+    }
+
+    pub fn decode(&self, src: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Encoding::UpperHex | Encoding::LowerHex => Ok(hex::decode(src)?),
+            Encoding::Base64 => Ok(BASE64_LENIENT.decode(src)?),
+            Encoding::Base64Url => Ok(BASE64_URL_LENIENT.decode(src)?),
+            Encoding::Raw => Ok(src.to_vec()),
+        }
+    }
+
+    /// Guesses the encoding of `src`, ignoring ASCII whitespace, and decodes it.
+    ///
+    /// Text that consists only of an even number of hex digits is read as hex, otherwise
+    /// it is read as Base64, or as Base64url if it contains `-` or `_`. Raw data is never
+    /// detected. A short Base64 text can happen to look like hex, so pass the encoding
+    /// explicitly when it is known.
+    pub fn detect(src: &[u8]) -> Result<Vec<u8>> {
+        let cleaned: Vec<u8> = src.iter().copied().filter(|b| !b.is_ascii_whitespace()).collect();
+        let encoding = if cleaned.len().is_multiple_of(2) && cleaned.iter().all(u8::is_ascii_hexdigit) {
+            Encoding::UpperHex
+        } else if cleaned.iter().any(|&b| b == b'-' || b == b'_') {
+            Encoding::Base64Url
+        } else {
+            Encoding::Base64
+        };
+        encoding.decode(&cleaned)
+    }
+}
+
+/// Encodes ciphertext for the string functions.
+pub(crate) fn encode_text(options: &Options, data: &[u8]) -> Result<String> {
+    if options.encoding == Encoding::Raw {
+        return Err(Error::NotText);
+    }
+    Ok(String::from_utf8(options.encoding.encode(data)).expect("text encodings are ASCII"))
+}
+
+/// Decodes ciphertext for the string functions.
+pub(crate) fn decode_text(options: &Options, src: &str) -> Result<Vec<u8>> {
+    if options.detect_encoding {
+        Encoding::detect(src.as_bytes())
+    } else if options.encoding == Encoding::Raw {
+        Err(Error::NotText)
+    } else {
+        options.encoding.decode(src.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: &[u8] = &[0xfb, 0xff, 0x00, 0x3e, 0x7f];
+
+    #[test]
+    fn test_encode() {
+        let cases: [(Encoding, &[u8]); 5] = [
+            (Encoding::UpperHex, b"FBFF003E7F"),
+            (Encoding::LowerHex, b"fbff003e7f"),
+            (Encoding::Base64, b"+/8APn8="),
+            (Encoding::Base64Url, b"-_8APn8"),
+            (Encoding::Raw, DATA),
+        ];
+        for (encoding, encoded) in cases {
+            assert_eq!(encoding.encode(DATA), encoded, "{:?}", encoding);
+            assert_eq!(encoding.decode(encoded), Ok(DATA.to_vec()), "{:?}", encoding);
+        }
+        assert_eq!(Encoding::Base64.decode(b"+/8APn8"), Ok(DATA.to_vec()));
+        assert_eq!(Encoding::Base64Url.decode(b"-_8APn8="), Ok(DATA.to_vec()));
+        assert_eq!(Encoding::UpperHex.decode(b"fbFF003e7F"), Ok(DATA.to_vec()));
+    }
+
+    #[test]
+    fn test_detect() {
+        for src in ["FBFF003E7F", "fbff003e7f", " FBFF\n003E\t7F\r\n", "+/8APn8=", "+/8A\nPn8", "-_8APn8"] {
+            assert_eq!(Encoding::detect(src.as_bytes()), Ok(DATA.to_vec()), "{:?}", src);
+        }
+        assert_eq!(Encoding::detect(b""), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(Encoding::UpperHex.decode(b"FBF"), Err(Error::OddLength));
+        assert_eq!(Encoding::Base64.decode(b"+/8*Pn8="), Err(Error::InvalidEncoding { c: '*', index: 3 }));
+        assert_eq!(Encoding::Base64.decode(b"-_8APn8"), Err(Error::InvalidEncoding { c: '-', index: 0 }));
+        assert_eq!(Encoding::Base64.decode(b"+/8AP"), Err(Error::InvalidLength));
+        assert_eq!(encode_text(&Options::new().encoding(Encoding::Raw), DATA), Err(Error::NotText));
+    }
+}
@@ -0,0 +1,148 @@
+use crate::consts::*;
+use crate::cts::{random_iv, BlowfishKey};
+use crate::error::{Error, Result};
+use crate::options::Options;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Layout: VERSION || IV || CTS ciphertext || HMAC-SHA256(VERSION || IV || CTS ciphertext)
+const VERSION: u8 = 1;
+const HEADER_SIZE: usize = 1 + BLOCK_SIZE;
+const TAG_SIZE: usize = 32;
+
+const ENCRYPTION_LABEL: &[u8] = b"blowfishj-rs v1 encryption";
+const AUTHENTICATION_LABEL: &[u8] = b"blowfishj-rs v1 authentication";
+
+/// A password set up for sealing and opening any number of envelopes.
+///
+/// Only the MAC key is kept, not an HMAC state built from it, so that both keys can be
+/// zeroized.
+#[derive(Zeroize, ZeroizeOnDrop)]
+pub(crate) struct Envelope {
+    key: BlowfishKey,
+    mac_key: Zeroizing<[u8; 32]>,
+}
+
+impl Envelope {
+    pub(crate) fn new(options: &Options, key: &[u8]) -> Result<Self> {
+        let mac_key = derive_key(key, AUTHENTICATION_LABEL);
+        let key = options.new_key(&derive_key(key, ENCRYPTION_LABEL)[..])?;
+        Ok(Self { key, mac_key })
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.mac_key[..]).expect("HMAC accepts keys of any length")
+    }
+
+    pub(crate) fn seal(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let iv = random_iv()?;
+
+        let len = data.len();
+        let mut sealed = vec![0u8; HEADER_SIZE + len + TAG_SIZE];
+        sealed[0] = VERSION;
+        sealed[1..HEADER_SIZE].copy_from_slice(&iv);
+        self.key.message_with_iv(&iv).encrypt(data, 0, &mut sealed, HEADER_SIZE, len)?;
+
+        let mut mac = self.mac();
+        mac.update(&sealed[..HEADER_SIZE + len]);
+        sealed[HEADER_SIZE + len..].copy_from_slice(&mac.finalize().into_bytes());
+        Ok(sealed)
+    }
+
+    pub(crate) fn open(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match data.first() {
+            None => return Err(Error::Truncated),
+            Some(&VERSION) => {}
+            Some(&version) => return Err(Error::UnsupportedVersion(version)),
+        }
+        if data.len() < HEADER_SIZE + TAG_SIZE {
+            return Err(Error::Truncated);
+        }
+
+        let len = data.len() - HEADER_SIZE - TAG_SIZE;
+        let (authenticated, tag) = data.split_at(HEADER_SIZE + len);
+        let mut mac = self.mac();
+        mac.update(authenticated);
+        mac.verify_slice(tag).map_err(|_| Error::AuthenticationFailed)?;
+
+        let iv = data[1..HEADER_SIZE].try_into().unwrap();
+        let mut opened = vec![0u8; len];
+        self.key.message_with_iv(iv).decrypt(data, HEADER_SIZE, &mut opened, 0, len)?;
+        Ok(opened)
+    }
+}
+
+fn derive_key(key: &[u8], label: &[u8]) -> Zeroizing<[u8; 32]> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(label);
+    Zeroizing::new(mac.finalize().into_bytes().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"foobar";
+    const TEXT: &[u8] = b"How I wish I could recollect PI easily using one trick?";
+
+    fn seal(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+        Envelope::new(&Options::default(), key)?.seal(data)
+    }
+
+    fn open(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+        Envelope::new(&Options::default(), key)?.open(data)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for len in [0, 1, 8, 13, TEXT.len()] {
+            let sealed = seal(KEY, &TEXT[..len]).unwrap();
+            assert_eq!(sealed.len(), HEADER_SIZE + len + TAG_SIZE);
+            assert_eq!(sealed[0], VERSION);
+            assert_eq!(open(KEY, &sealed), Ok(TEXT[..len].to_vec()));
+        }
+        assert_ne!(seal(KEY, TEXT).unwrap(), seal(KEY, TEXT).unwrap());
+    }
+
+    #[test]
+    fn test_zeroize() {
+        let mut envelope = Envelope::new(&Options::default(), KEY).unwrap();
+        envelope.zeroize();
+        assert_eq!(*envelope.mac_key, [0; 32]);
+    }
+
+    #[test]
+    fn test_reuse() {
+        let envelope = Envelope::new(&Options::default(), KEY).unwrap();
+        let sealed: Vec<_> = (0..3).map(|_| envelope.seal(TEXT).unwrap()).collect();
+        for sealed in sealed.iter().rev() {
+            assert_eq!(envelope.open(sealed), Ok(TEXT.to_vec()));
+            assert_eq!(open(KEY, sealed), Ok(TEXT.to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_tampering() {
+        let sealed = seal(KEY, TEXT).unwrap();
+        for i in 1..sealed.len() {
+            let mut tampered = sealed.clone();
+            tampered[i] ^= 0x01;
+            assert_eq!(open(KEY, &tampered), Err(Error::AuthenticationFailed), "byte {}", i);
+        }
+        assert_eq!(open(b"wrong", &sealed), Err(Error::AuthenticationFailed));
+    }
+
+    #[test]
+    fn test_malformed() {
+        let sealed = seal(KEY, b"").unwrap();
+        assert_eq!(open(KEY, &[]), Err(Error::Truncated));
+        assert_eq!(open(KEY, &sealed[..sealed.len() - 1]), Err(Error::Truncated));
+
+        let mut other_version = sealed.clone();
+        other_version[0] = 2;
+        assert_eq!(open(KEY, &other_version), Err(Error::UnsupportedVersion(2)));
+    }
+}
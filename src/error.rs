@@ -0,0 +1,128 @@
+use std::fmt;
+use std::io;
+use std::string::FromUtf8Error;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The encrypted text contains a character that is not valid in its encoding.
+    InvalidEncoding { c: char, index: usize },
+    /// The encrypted text has an odd number of hex digits.
+    OddLength,
+    /// The encrypted text has a length that its encoding never produces.
+    InvalidLength,
+    /// [`Encoding::Raw`](crate::Encoding::Raw) was selected for a function that works on text.
+    NotText,
+    /// The decrypted bytes are not valid UTF-8, usually because the key is wrong.
+    /// The raw bytes are available through [`FromUtf8Error::as_bytes`].
+    InvalidUtf8(FromUtf8Error),
+    /// The decrypted bytes are not valid in the given charset, usually because the key is
+    /// wrong.
+    InvalidText { charset: &'static str },
+    /// The text or password contains a character the given charset cannot represent.
+    UnmappableText { charset: &'static str },
+    /// The key is empty.
+    EmptyKey,
+    /// A buffer is too small for the requested position and length.
+    BufferTooSmall { needed: usize, available: usize },
+    /// The data length is not a multiple of the block size.
+    NotBlockAligned(usize),
+    /// The key expands to S-boxes with duplicate entries and weak keys are rejected.
+    WeakKey,
+    /// The cipher produced a wrong result for a known-answer test vector.
+    SelfTestFailed,
+    /// A raw key is not between 4 and 56 bytes long.
+    InvalidKeyLength(usize),
+    /// The salted header is missing or malformed, names an unknown algorithm or mode, or
+    /// asks for more work than [`decrypt_with`](crate::decrypt_with) is willing to do.
+    InvalidHeader,
+    /// The [`PasswordHash`](crate::PasswordHash) parameters are zero, invalid for Argon2, or
+    /// above what [`decrypt_with`](crate::decrypt_with) accepts.
+    InvalidPasswordHash,
+    /// The padding of the last block is invalid, usually because the key is wrong.
+    InvalidPadding,
+    /// The mode needs an IV and none was set, or ECB was given one.
+    InvalidIv,
+    /// The ciphertext is too short for the selected mode.
+    Truncated,
+    /// The operating system's random number generator failed.
+    RandomUnavailable,
+    /// The authentication tag does not match: the key is wrong or the data was modified.
+    AuthenticationFailed,
+    /// The envelope was written by an unknown format version.
+    UnsupportedVersion(u8),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidEncoding { c, index } => write!(f, "invalid character {:?} at position {}", c, index),
+            Error::OddLength => write!(f, "odd number of digits"),
+            Error::InvalidLength => write!(f, "invalid length for the encoding"),
+            Error::NotText => write!(f, "raw encoding cannot be used for text"),
+            Error::InvalidUtf8(e) => write!(f, "decrypted text is not valid UTF-8: {}", e),
+            Error::InvalidText { charset } => write!(f, "decrypted text is not valid {}", charset),
+            Error::UnmappableText { charset } => write!(f, "text cannot be represented in {}", charset),
+            Error::EmptyKey => write!(f, "key is empty"),
+            Error::BufferTooSmall { needed, available } => {
+                write!(f, "buffer too small: need {} bytes, have {}", needed, available)
+            }
+            Error::NotBlockAligned(len) => write!(f, "length {} is not a multiple of the block size", len),
+            Error::WeakKey => write!(f, "weak key"),
+            Error::SelfTestFailed => write!(f, "self test failed"),
+            Error::InvalidKeyLength(len) => write!(f, "invalid key length {}, expected 4 to 56 bytes", len),
+            Error::InvalidHeader => write!(f, "invalid salted header"),
+            Error::InvalidPasswordHash => write!(f, "invalid password hash parameters"),
+            Error::InvalidPadding => write!(f, "invalid padding"),
+            Error::InvalidIv => write!(f, "missing or unexpected IV"),
+            Error::Truncated => write!(f, "ciphertext is too short"),
+            Error::RandomUnavailable => write!(f, "random number generator unavailable"),
+            Error::AuthenticationFailed => write!(f, "authentication failed"),
+            Error::UnsupportedVersion(v) => write!(f, "unsupported format version {}", v),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::InvalidUtf8(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<hex::FromHexError> for Error {
+    fn from(e: hex::FromHexError) -> Self {
+        match e {
+            hex::FromHexError::InvalidHexCharacter { c, index } => Error::InvalidEncoding { c, index },
+            hex::FromHexError::OddLength => Error::OddLength,
+            hex::FromHexError::InvalidStringLength => Error::OddLength,
+        }
+    }
+}
+
+impl From<base64::DecodeError> for Error {
+    fn from(e: base64::DecodeError) -> Self {
+        match e {
+            base64::DecodeError::InvalidByte(index, b) | base64::DecodeError::InvalidLastSymbol(index, b) => {
+                Error::InvalidEncoding { c: b as char, index }
+            }
+            base64::DecodeError::InvalidLength(_) | base64::DecodeError::InvalidPadding => Error::InvalidLength,
+        }
+    }
+}
+
+impl From<FromUtf8Error> for Error {
+    fn from(e: FromUtf8Error) -> Self {
+        Error::InvalidUtf8(e)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, e)
+    }
+}
@@ -0,0 +1,302 @@
+use crate::cbc::BlowfishCBC;
+use crate::consts::*;
+use crate::cts::random_iv;
+use crate::ecb::BlowfishECB;
+use crate::error::{Error, Result};
+use crate::modes::{cfb64_decrypt, cfb64_encrypt, cfb8_decrypt, cfb8_encrypt, ofb64};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+// SunJCE rejects keys longer than 448 bits in `Cipher.init`. The 32-bit minimum is only
+// enforced by its `KeyGenerator`, so shorter keys are accepted here as well.
+const MAX_KEY_SIZE: usize = 56;
+
+/// The modes of `Cipher.getInstance("Blowfish/<mode>/<padding>")`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JceMode {
+    /// `ECB`, the default of `Cipher.getInstance("Blowfish")`.
+    #[default]
+    Ecb,
+    /// `CBC`.
+    Cbc,
+    /// `CFB8`.
+    Cfb8,
+    /// `CFB` or `CFB64`.
+    Cfb64,
+    /// `OFB` or `OFB64`.
+    Ofb,
+}
+
+/// The paddings of `Cipher.getInstance("Blowfish/<mode>/<padding>")`. As in SunJCE, a
+/// padding pads to whole 8-byte blocks in every mode, including the stream modes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JcePadding {
+    /// `PKCS5Padding`, the default: 1 to 8 bytes that each hold the number of pad bytes.
+    #[default]
+    Pkcs5,
+    /// `ISO10126Padding`: random bytes followed by the number of pad bytes.
+    Iso10126,
+    /// `NoPadding`. ECB and CBC then only accept whole blocks.
+    NoPadding,
+}
+
+/// Blowfish as configured by `Cipher.getInstance("Blowfish/<mode>/<padding>")` in SunJCE,
+/// with the key bytes used as is.
+///
+/// Like a `Cipher` initialized for encryption without parameters, the first
+/// [`encrypt`](Self::encrypt) in a mode other than ECB draws a random IV, which
+/// [`iv`](Self::iv) returns and later calls reuse until [`set_iv`](Self::set_iv) is called.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct JceBlowfish {
+    ecb: BlowfishECB,
+    #[zeroize(skip)]
+    mode: JceMode,
+    #[zeroize(skip)]
+    padding: JcePadding,
+    iv: Option<[u8; BLOCK_SIZE]>,
+}
+
+impl JceBlowfish {
+    /// Fails with [`Error::InvalidKeyLength`] for keys longer than 448 bits, like
+    /// `Cipher.init`.
+    pub fn new(mode: JceMode, padding: JcePadding, key: &[u8]) -> Result<Self> {
+        if key.len() > MAX_KEY_SIZE {
+            return Err(Error::InvalidKeyLength(key.len()));
+        }
+        Ok(Self { ecb: BlowfishECB::with_key(key)?, mode, padding, iv: None })
+    }
+
+    pub fn iv(&self) -> Option<[u8; BLOCK_SIZE]> {
+        self.iv
+    }
+
+    /// Sets the IV, like an `IvParameterSpec`. Fails with [`Error::InvalidIv`] in ECB mode,
+    /// which cannot use an IV.
+    pub fn set_iv(&mut self, iv: &[u8; BLOCK_SIZE]) -> Result<()> {
+        if self.mode == JceMode::Ecb {
+            return Err(Error::InvalidIv);
+        }
+        self.iv = Some(*iv);
+        Ok(())
+    }
+
+    /// Pads and encrypts `data`, like `doFinal`.
+    pub fn encrypt(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        if self.mode != JceMode::Ecb && self.iv.is_none() {
+            self.iv = Some(random_iv()?);
+        }
+
+        let mut encrypted_buf = data.to_vec();
+        match self.padding {
+            JcePadding::Pkcs5 => {
+                let pad = BLOCK_SIZE - data.len() % BLOCK_SIZE;
+                encrypted_buf.resize(data.len() + pad, pad as u8);
+            }
+            JcePadding::Iso10126 => {
+                let pad = BLOCK_SIZE - data.len() % BLOCK_SIZE;
+                encrypted_buf.resize(data.len() + pad, 0);
+                let len = encrypted_buf.len();
+                getrandom::fill(&mut encrypted_buf[len - pad..len - 1]).map_err(|_| Error::RandomUnavailable)?;
+                encrypted_buf[len - 1] = pad as u8;
+            }
+            JcePadding::NoPadding => self.check_aligned(data.len())?,
+        }
+
+        let iv = self.iv.unwrap_or_default();
+        match self.mode {
+            JceMode::Ecb => self.ecb.encrypt_blocks(&mut encrypted_buf)?,
+            JceMode::Cbc => {
+                let plain = Zeroizing::new(encrypted_buf.clone());
+                BlowfishCBC::from_ecb(self.ecb.clone(), iv).encrypt(&plain, 0, &mut encrypted_buf, 0, plain.len())?;
+            }
+            JceMode::Cfb8 => cfb8_encrypt(&self.ecb, iv, &mut encrypted_buf),
+            JceMode::Cfb64 => cfb64_encrypt(&self.ecb, iv, &mut encrypted_buf),
+            JceMode::Ofb => ofb64(&self.ecb, iv, &mut encrypted_buf),
+        }
+        Ok(encrypted_buf)
+    }
+
+    /// Decrypts `data` and removes the padding, like `doFinal`. Fails with
+    /// [`Error::InvalidIv`] if a mode other than ECB has no IV, with
+    /// [`Error::NotBlockAligned`] if a padded or block mode gets partial blocks, and with
+    /// [`Error::InvalidPadding`] where SunJCE throws `BadPaddingException`.
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let iv = match (self.mode, self.iv) {
+            (JceMode::Ecb, _) => [0; BLOCK_SIZE],
+            (_, Some(iv)) => iv,
+            (_, None) => return Err(Error::InvalidIv),
+        };
+        if self.padding != JcePadding::NoPadding && !data.len().is_multiple_of(BLOCK_SIZE) {
+            return Err(Error::NotBlockAligned(data.len()));
+        }
+        self.check_aligned(data.len())?;
+
+        let mut decrypted_buf = data.to_vec();
+        match self.mode {
+            JceMode::Ecb => self.ecb.decrypt_blocks(&mut decrypted_buf)?,
+            JceMode::Cbc => {
+                BlowfishCBC::from_ecb(self.ecb.clone(), iv).decrypt(data, 0, &mut decrypted_buf, 0, data.len())?;
+            }
+            JceMode::Cfb8 => cfb8_decrypt(&self.ecb, iv, &mut decrypted_buf),
+            JceMode::Cfb64 => cfb64_decrypt(&self.ecb, iv, &mut decrypted_buf),
+            JceMode::Ofb => ofb64(&self.ecb, iv, &mut decrypted_buf),
+        }
+
+        if self.padding != JcePadding::NoPadding && !decrypted_buf.is_empty() {
+            let len = decrypted_buf.len();
+            let pad = decrypted_buf[len - 1] as usize;
+            let valid = match self.padding {
+                JcePadding::Pkcs5 => {
+                    (1..=BLOCK_SIZE).contains(&pad) && decrypted_buf[len - pad..].iter().all(|&b| b as usize == pad)
+                }
+                _ => (1..=BLOCK_SIZE).contains(&pad),
+            };
+            if !valid {
+                decrypted_buf.zeroize();
+                return Err(Error::InvalidPadding);
+            }
+            decrypted_buf.truncate(len - pad);
+        }
+        Ok(decrypted_buf)
+    }
+
+    fn check_aligned(&self, len: usize) -> Result<()> {
+        let block_mode = matches!(self.mode, JceMode::Ecb | JceMode::Cbc);
+        if block_mode && !len.is_multiple_of(BLOCK_SIZE) {
+            return Err(Error::NotBlockAligned(len));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"partner-secret!!";
+    const IV: &[u8; BLOCK_SIZE] = &[0xfe, 0xdc, 0xba, 0x98, 0x76, 0x54, 0x32, 0x10];
+    const TEXT: &[u8] = b"How I wish I could recollect PI easily";
+
+    // Produced by SunJCE (Java 17). ISO10126 output contains random bytes and is only
+    // decrypted.
+    const FIXTURES: &[(JceMode, JcePadding, usize, &str)] = &[
+        (JceMode::Ecb, JcePadding::Pkcs5, 0, "7143F39C1A721945"),
+        (JceMode::Ecb, JcePadding::Pkcs5, 5, "CA7CBACE60D68EE5"),
+        (JceMode::Ecb, JcePadding::Pkcs5, 13, "CFC6FA5C909386DF181B0A95388C5BDC"),
+        (JceMode::Ecb, JcePadding::Pkcs5, 16, "CFC6FA5C909386DFDF310C85597E18827143F39C1A721945"),
+        (JceMode::Ecb, JcePadding::NoPadding, 0, ""),
+        (JceMode::Ecb, JcePadding::NoPadding, 16, "CFC6FA5C909386DFDF310C85597E1882"),
+        (JceMode::Ecb, JcePadding::Iso10126, 0, "BFAF61FB7CEF8A20"),
+        (JceMode::Ecb, JcePadding::Iso10126, 5, "90509FC76392E661"),
+        (JceMode::Ecb, JcePadding::Iso10126, 13, "CFC6FA5C909386DF4B8DCFF607519590"),
+        (JceMode::Ecb, JcePadding::Iso10126, 16, "CFC6FA5C909386DFDF310C85597E1882439A9D0A50548FBF"),
+        (JceMode::Cbc, JcePadding::Pkcs5, 0, "0F3172B2F9E579D5"),
+        (JceMode::Cbc, JcePadding::Pkcs5, 5, "0C110FEE65962BC7"),
+        (JceMode::Cbc, JcePadding::Pkcs5, 13, "61B8D5DBFCC2147B38B865F444C87474"),
+        (JceMode::Cbc, JcePadding::Pkcs5, 16, "61B8D5DBFCC2147B789193E538343ECA4FA13426F5B48D69"),
+        (JceMode::Cbc, JcePadding::NoPadding, 0, ""),
+        (JceMode::Cbc, JcePadding::NoPadding, 16, "61B8D5DBFCC2147B789193E538343ECA"),
+        (JceMode::Cbc, JcePadding::Iso10126, 0, "B083FAC637D36AE7"),
+        (JceMode::Cbc, JcePadding::Iso10126, 5, "376C1B411440B5A7"),
+        (JceMode::Cbc, JcePadding::Iso10126, 13, "61B8D5DBFCC2147B008AF62CAE9729F2"),
+        (JceMode::Cbc, JcePadding::Iso10126, 16, "61B8D5DBFCC2147B789193E538343ECA5A25634FF5558DD2"),
+        (JceMode::Cfb8, JcePadding::Pkcs5, 0, "DC84CB0D3A187F59"),
+        (JceMode::Cfb8, JcePadding::Pkcs5, 5, "9C5CFBE6DA9143D4"),
+        (JceMode::Cfb8, JcePadding::Pkcs5, 13, "9C5CFBE6DAB2E788043C3C658149D140"),
+        (JceMode::Cfb8, JcePadding::Pkcs5, 16, "9C5CFBE6DAB2E788043C3C65812978455721200C9AB4A9C4"),
+        (JceMode::Cfb8, JcePadding::NoPadding, 0, ""),
+        (JceMode::Cfb8, JcePadding::NoPadding, 5, "9C5CFBE6DA"),
+        (JceMode::Cfb8, JcePadding::NoPadding, 13, "9C5CFBE6DAB2E788043C3C6581"),
+        (JceMode::Cfb8, JcePadding::NoPadding, 16, "9C5CFBE6DAB2E788043C3C6581297845"),
+        (JceMode::Cfb8, JcePadding::Iso10126, 0, "92443646CD45D645"),
+        (JceMode::Cfb8, JcePadding::Iso10126, 5, "9C5CFBE6DA48103B"),
+        (JceMode::Cfb8, JcePadding::Iso10126, 13, "9C5CFBE6DAB2E788043C3C65810AEB83"),
+        (JceMode::Cfb8, JcePadding::Iso10126, 16, "9C5CFBE6DAB2E788043C3C65812978453654510B0D62F840"),
+        (JceMode::Cfb64, JcePadding::Pkcs5, 0, "DCC83FFBCF1252FC"),
+        (JceMode::Cfb64, JcePadding::Pkcs5, 5, "9CAF40D38E1959F7"),
+        (JceMode::Cfb64, JcePadding::Pkcs5, 13, "9CAF40D38E3A2D9D2F03738B4E0B7595"),
+        (JceMode::Cfb64, JcePadding::Pkcs5, 16, "9CAF40D38E3A2D9D2F03738B4E6B19E3CDB572030FDB1CF2"),
+        (JceMode::Cfb64, JcePadding::NoPadding, 0, ""),
+        (JceMode::Cfb64, JcePadding::NoPadding, 5, "9CAF40D38E"),
+        (JceMode::Cfb64, JcePadding::NoPadding, 13, "9CAF40D38E3A2D9D2F03738B4E"),
+        (JceMode::Cfb64, JcePadding::NoPadding, 16, "9CAF40D38E3A2D9D2F03738B4E6B19E3"),
+        (JceMode::Cfb64, JcePadding::Iso10126, 0, "55CDBFA5A81FA8FC"),
+        (JceMode::Cfb64, JcePadding::Iso10126, 5, "9CAF40D38EC971F7"),
+        (JceMode::Cfb64, JcePadding::Iso10126, 13, "9CAF40D38E3A2D9D2F03738B4E83E095"),
+        (JceMode::Cfb64, JcePadding::Iso10126, 16, "9CAF40D38E3A2D9D2F03738B4E6B19E30EF79704BC216DF2"),
+        (JceMode::Ofb, JcePadding::Pkcs5, 0, "DCC83FFBCF1252FC"),
+        (JceMode::Ofb, JcePadding::Pkcs5, 5, "9CAF40D38E1959F7"),
+        (JceMode::Ofb, JcePadding::Pkcs5, 13, "9CAF40D38E3A2D9D983CB105FCCD31E7"),
+        (JceMode::Ofb, JcePadding::Pkcs5, 16, "9CAF40D38E3A2D9D983CB105FCAD5D91792A3B11B9D51D2F"),
+        (JceMode::Ofb, JcePadding::NoPadding, 0, ""),
+        (JceMode::Ofb, JcePadding::NoPadding, 5, "9CAF40D38E"),
+        (JceMode::Ofb, JcePadding::NoPadding, 13, "9CAF40D38E3A2D9D983CB105FC"),
+        (JceMode::Ofb, JcePadding::NoPadding, 16, "9CAF40D38E3A2D9D983CB105FCAD5D91"),
+        (JceMode::Ofb, JcePadding::Iso10126, 0, "B0FFB127C843FEFC"),
+        (JceMode::Ofb, JcePadding::Iso10126, 5, "9CAF40D38E0FA6F7"),
+        (JceMode::Ofb, JcePadding::Iso10126, 13, "9CAF40D38E3A2D9D983CB105FC94B4E7"),
+        (JceMode::Ofb, JcePadding::Iso10126, 16, "9CAF40D38E3A2D9D983CB105FCAD5D9141F089B57FD6F12F"),
+    ];
+
+    fn cipher(mode: JceMode, padding: JcePadding) -> JceBlowfish {
+        let mut cipher = JceBlowfish::new(mode, padding, KEY).unwrap();
+        if mode != JceMode::Ecb {
+            cipher.set_iv(IV).unwrap();
+        }
+        cipher
+    }
+
+    #[test]
+    fn test_fixtures() {
+        for &(mode, padding, len, encrypted) in FIXTURES {
+            let encrypted = hex::decode(encrypted).unwrap();
+            let mut cipher = cipher(mode, padding);
+            assert_eq!(cipher.decrypt(&encrypted).as_deref(), Ok(&TEXT[..len]), "{:?} {:?} {}", mode, padding, len);
+            let got = cipher.encrypt(&TEXT[..len]).unwrap();
+            if padding == JcePadding::Iso10126 {
+                assert_eq!(got.len(), encrypted.len());
+                assert_eq!(cipher.decrypt(&got).as_deref(), Ok(&TEXT[..len]));
+            } else {
+                assert_eq!(got, encrypted, "{:?} {:?} {}", mode, padding, len);
+            }
+        }
+    }
+
+    #[test]
+    fn test_key_length() {
+        assert_eq!(JceBlowfish::new(JceMode::Ecb, JcePadding::Pkcs5, b"").err(), Some(Error::EmptyKey));
+        assert!(JceBlowfish::new(JceMode::Ecb, JcePadding::Pkcs5, b"abc").is_ok());
+        assert!(JceBlowfish::new(JceMode::Ecb, JcePadding::Pkcs5, &[0; 56]).is_ok());
+        assert_eq!(JceBlowfish::new(JceMode::Ecb, JcePadding::Pkcs5, &[0; 57]).err(), Some(Error::InvalidKeyLength(57)));
+    }
+
+    #[test]
+    fn test_iv() {
+        let mut ecb = cipher(JceMode::Ecb, JcePadding::Pkcs5);
+        assert_eq!(ecb.set_iv(IV), Err(Error::InvalidIv));
+        assert_eq!(ecb.iv(), None);
+
+        let mut cbc = JceBlowfish::new(JceMode::Cbc, JcePadding::Pkcs5, KEY).unwrap();
+        assert_eq!(cbc.decrypt(&[0; 8]), Err(Error::InvalidIv));
+        let encrypted = cbc.encrypt(TEXT).unwrap();
+        let iv = cbc.iv().unwrap();
+        assert_eq!(cbc.encrypt(TEXT).unwrap(), encrypted);
+
+        let mut other = JceBlowfish::new(JceMode::Cbc, JcePadding::Pkcs5, KEY).unwrap();
+        other.set_iv(&iv).unwrap();
+        assert_eq!(other.decrypt(&encrypted).as_deref(), Ok(TEXT));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut ecb = cipher(JceMode::Ecb, JcePadding::NoPadding);
+        assert_eq!(ecb.encrypt(&TEXT[..5]), Err(Error::NotBlockAligned(5)));
+        assert_eq!(ecb.decrypt(&[0; 5]), Err(Error::NotBlockAligned(5)));
+        assert_eq!(cipher(JceMode::Ofb, JcePadding::Pkcs5).decrypt(&[0; 5]), Err(Error::NotBlockAligned(5)));
+
+        let pkcs5 = cipher(JceMode::Cbc, JcePadding::Pkcs5);
+        let mut tampered = hex::decode("0C110FEE65962BC7").unwrap();
+        tampered[7] ^= 1;
+        assert_eq!(pkcs5.decrypt(&tampered), Err(Error::InvalidPadding));
+        assert_eq!(pkcs5.decrypt(&[]), Ok(Vec::new()));
+    }
+}
@@ -1,7 +1,51 @@
+mod blocks;
 mod blowfishj;
+#[cfg(feature = "cache")]
+mod cache;
+mod cbc;
+mod cbc_stream;
+mod charset;
 mod cts;
+mod easy;
 mod ecb;
+mod encoding;
 mod consts;
+mod envelope;
+mod error;
+mod jce;
+mod modes;
+mod openssl;
+mod options;
+mod php;
+#[cfg(feature = "cipher")]
+mod rustcrypto;
+mod salted;
+mod stream;
+
+pub use encoding_rs;
 
 pub use blowfishj::encrypt;
 pub use blowfishj::decrypt;
+pub use blowfishj::encrypt_bytes;
+pub use blowfishj::decrypt_bytes;
+pub use blowfishj::{decrypt_bytes_with, decrypt_with, encrypt_bytes_with, encrypt_with};
+pub use blowfishj::{decrypt_many, decrypt_many_with, encrypt_many, encrypt_many_with};
+pub use blowfishj::{decrypt_charset, decrypt_charset_with, encrypt_charset, encrypt_charset_with};
+pub use blowfishj::{encrypt_salted, encrypt_salted_with};
+pub use blowfishj::{decrypt_bytes_zeroizing, decrypt_bytes_zeroizing_with, decrypt_zeroizing, decrypt_zeroizing_with};
+#[cfg(feature = "cache")]
+pub use cache::{CacheStats, KeyCache};
+pub use cbc::BlowfishCBC;
+pub use cbc_stream::{BlowfishInputStreamReader, BlowfishOutputStreamWriter};
+pub use consts::BLOCK_SIZE;
+pub use cts::{BlowfishCTS, BlowfishKey, CtsMessage};
+pub use easy::BlowfishEasy;
+pub use ecb::BlowfishECB;
+pub use encoding::Encoding;
+pub use error::{Error, Result};
+pub use jce::{JceBlowfish, JceMode, JcePadding};
+pub use openssl::{OpensslDigest, OpensslEnc, OpensslKdf, OpensslMode};
+pub use options::{KeyDerivation, Mode, Options};
+pub use php::{PhpBlowfish, PhpMode};
+pub use salted::PasswordHash;
+pub use stream::{CtsReader, CtsWriter};
@@ -0,0 +1,57 @@
+use crate::consts::*;
+use crate::ecb::BlowfishECB;
+
+// Stream modes shared by the OpenSSL and JCE profiles. Each works in place on data of any
+// length, starting from the given IV.
+
+pub(crate) fn cfb64_encrypt(ecb: &BlowfishECB, mut feedback: [u8; BLOCK_SIZE], data: &mut [u8]) {
+    for chunk in data.chunks_mut(BLOCK_SIZE) {
+        ecb.encrypt_block(&mut feedback);
+        for (b, k) in chunk.iter_mut().zip(feedback.iter_mut()) {
+            *b ^= *k;
+            *k = *b;
+        }
+    }
+}
+
+pub(crate) fn cfb64_decrypt(ecb: &BlowfishECB, mut feedback: [u8; BLOCK_SIZE], data: &mut [u8]) {
+    for chunk in data.chunks_mut(BLOCK_SIZE) {
+        ecb.encrypt_block(&mut feedback);
+        for (b, k) in chunk.iter_mut().zip(feedback.iter_mut()) {
+            let c = *b;
+            *b ^= *k;
+            *k = c;
+        }
+    }
+}
+
+pub(crate) fn ofb64(ecb: &BlowfishECB, mut feedback: [u8; BLOCK_SIZE], data: &mut [u8]) {
+    for chunk in data.chunks_mut(BLOCK_SIZE) {
+        ecb.encrypt_block(&mut feedback);
+        for (b, k) in chunk.iter_mut().zip(feedback.iter()) {
+            *b ^= *k;
+        }
+    }
+}
+
+/// 8-bit CFB: each byte is XORed with the first byte of the encrypted shift register, which
+/// then shifts in the ciphertext byte.
+pub(crate) fn cfb8_encrypt(ecb: &BlowfishECB, mut register: [u8; BLOCK_SIZE], data: &mut [u8]) {
+    for b in data {
+        let mut keystream = register;
+        ecb.encrypt_block(&mut keystream);
+        *b ^= keystream[0];
+        register.copy_within(1.., 0);
+        register[BLOCK_SIZE - 1] = *b;
+    }
+}
+
+pub(crate) fn cfb8_decrypt(ecb: &BlowfishECB, mut register: [u8; BLOCK_SIZE], data: &mut [u8]) {
+    for b in data {
+        let mut keystream = register;
+        ecb.encrypt_block(&mut keystream);
+        register.copy_within(1.., 0);
+        register[BLOCK_SIZE - 1] = *b;
+        *b ^= keystream[0];
+    }
+}
@@ -0,0 +1,231 @@
+use crate::cbc::BlowfishCBC;
+use crate::consts::*;
+use crate::cts::random_iv;
+use crate::ecb::BlowfishECB;
+use crate::error::{Error, Result};
+use crate::modes::{cfb64_decrypt, cfb64_encrypt, ofb64};
+use md5::Md5;
+use sha2::digest::{Digest, FixedOutputReset};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+// Layout: "Salted__" || salt (8 bytes) || ciphertext
+const MAGIC: &[u8; 8] = b"Salted__";
+const SALT_SIZE: usize = 8;
+const HEADER_SIZE: usize = MAGIC.len() + SALT_SIZE;
+// `openssl enc` uses 128-bit Blowfish keys.
+const KEY_SIZE: usize = 16;
+
+/// The Blowfish ciphers of `openssl enc`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OpensslMode {
+    /// `-bf-cbc`, with PKCS#7 padding.
+    #[default]
+    Cbc,
+    /// `-bf-cfb`, 64-bit cipher feedback without padding.
+    Cfb64,
+    /// `-bf-ofb`, 64-bit output feedback without padding.
+    Ofb64,
+}
+
+/// The digest selected with `-md`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OpensslDigest {
+    /// The default before OpenSSL 1.1.0.
+    Md5,
+    /// The default since OpenSSL 1.1.0.
+    #[default]
+    Sha256,
+}
+
+/// How `openssl enc` derives the key and IV from the password and the salt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpensslKdf {
+    /// `EVP_BytesToKey` with a single iteration, used unless `-pbkdf2` or `-iter` is given.
+    BytesToKey(OpensslDigest),
+    /// PBKDF2-HMAC with the given number of iterations, as with `-pbkdf2 -iter N`. OpenSSL
+    /// uses 10000 iterations if `-iter` is not given.
+    Pbkdf2 { digest: OpensslDigest, iterations: u32 },
+}
+
+impl Default for OpensslKdf {
+    fn default() -> Self {
+        OpensslKdf::BytesToKey(OpensslDigest::default())
+    }
+}
+
+/// Reads and writes the binary output of `openssl enc -bf-cbc`, `-bf-cfb` and `-bf-ofb`
+/// with a salt. For `-a` output, decode the Base64 first, for example with
+/// [`Encoding::detect`](crate::Encoding::detect).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpensslEnc {
+    mode: OpensslMode,
+    kdf: OpensslKdf,
+}
+
+impl OpensslEnc {
+    pub fn new(mode: OpensslMode, kdf: OpensslKdf) -> Self {
+        Self { mode, kdf }
+    }
+
+    /// Encrypts `data` with a random salt.
+    pub fn encrypt(&self, password: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+        self.encrypt_with_salt(password, &random_iv()?, data)
+    }
+
+    /// Encrypts `data` with the given salt, like `-S`. The key and IV are derived from the
+    /// password and salt, so reusing a salt with the same password reuses both.
+    pub fn encrypt_with_salt(&self, password: &[u8], salt: &[u8; SALT_SIZE], data: &[u8]) -> Result<Vec<u8>> {
+        let (ecb, iv) = self.derive(password, salt)?;
+
+        let mut encrypted_buf = Vec::with_capacity(HEADER_SIZE + data.len() + BLOCK_SIZE);
+        encrypted_buf.extend_from_slice(MAGIC);
+        encrypted_buf.extend_from_slice(salt);
+        match self.mode {
+            OpensslMode::Cbc => {
+                let pad = BLOCK_SIZE - data.len() % BLOCK_SIZE;
+                let mut padded = Zeroizing::new(Vec::with_capacity(data.len() + pad));
+                padded.extend_from_slice(data);
+                padded.resize(data.len() + pad, pad as u8);
+
+                let mut cbc = BlowfishCBC::from_ecb(ecb, iv);
+                encrypted_buf.resize(HEADER_SIZE + padded.len(), 0);
+                cbc.encrypt(&padded, 0, &mut encrypted_buf, HEADER_SIZE, padded.len())?;
+            }
+            OpensslMode::Cfb64 => {
+                encrypted_buf.extend_from_slice(data);
+                cfb64_encrypt(&ecb, iv, &mut encrypted_buf[HEADER_SIZE..]);
+            }
+            OpensslMode::Ofb64 => {
+                encrypted_buf.extend_from_slice(data);
+                ofb64(&ecb, iv, &mut encrypted_buf[HEADER_SIZE..]);
+            }
+        }
+        Ok(encrypted_buf)
+    }
+
+    /// Decrypts the output of `openssl enc`. Fails with [`Error::InvalidHeader`] if the
+    /// data does not start with `Salted__`, and for CBC with [`Error::InvalidPadding`] if the
+    /// padding is wrong, which usually means the password or settings are wrong.
+    pub fn decrypt(&self, password: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+        if data.len() < HEADER_SIZE {
+            return Err(if MAGIC.starts_with(data) { Error::Truncated } else { Error::InvalidHeader });
+        }
+        let (header, body) = data.split_at(HEADER_SIZE);
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(Error::InvalidHeader);
+        }
+        let salt = header[MAGIC.len()..].try_into().unwrap();
+        let (ecb, iv) = self.derive(password, salt)?;
+
+        let mut decrypted_buf = body.to_vec();
+        match self.mode {
+            OpensslMode::Cbc => {
+                if body.is_empty() {
+                    return Err(Error::Truncated);
+                }
+                if !body.len().is_multiple_of(BLOCK_SIZE) {
+                    return Err(Error::NotBlockAligned(body.len()));
+                }
+                let mut cbc = BlowfishCBC::from_ecb(ecb, iv);
+                cbc.decrypt(body, 0, &mut decrypted_buf, 0, body.len())?;
+
+                let pad = *decrypted_buf.last().unwrap() as usize;
+                let len = decrypted_buf.len();
+                if pad == 0 || pad > BLOCK_SIZE || decrypted_buf[len - pad..].iter().any(|&b| b as usize != pad) {
+                    zeroize::Zeroize::zeroize(&mut decrypted_buf);
+                    return Err(Error::InvalidPadding);
+                }
+                decrypted_buf.truncate(len - pad);
+            }
+            OpensslMode::Cfb64 => cfb64_decrypt(&ecb, iv, &mut decrypted_buf),
+            OpensslMode::Ofb64 => ofb64(&ecb, iv, &mut decrypted_buf),
+        }
+        Ok(decrypted_buf)
+    }
+
+    fn derive(&self, password: &[u8], salt: &[u8; SALT_SIZE]) -> Result<(BlowfishECB, [u8; BLOCK_SIZE])> {
+        let mut derived = Zeroizing::new([0u8; KEY_SIZE + BLOCK_SIZE]);
+        match self.kdf {
+            OpensslKdf::BytesToKey(OpensslDigest::Md5) => bytes_to_key::<Md5>(password, salt, &mut derived[..]),
+            OpensslKdf::BytesToKey(OpensslDigest::Sha256) => bytes_to_key::<Sha256>(password, salt, &mut derived[..]),
+            OpensslKdf::Pbkdf2 { digest: OpensslDigest::Md5, iterations } => {
+                pbkdf2::pbkdf2_hmac::<Md5>(password, salt, iterations, &mut derived[..])
+            }
+            OpensslKdf::Pbkdf2 { digest: OpensslDigest::Sha256, iterations } => {
+                pbkdf2::pbkdf2_hmac::<Sha256>(password, salt, iterations, &mut derived[..])
+            }
+        }
+        let ecb = BlowfishECB::with_key(&derived[..KEY_SIZE])?;
+        Ok((ecb, derived[KEY_SIZE..].try_into().unwrap()))
+    }
+}
+
+/// `EVP_BytesToKey` with one iteration: D_i = H(D_(i-1) || password || salt), concatenated
+/// until `out` is full.
+fn bytes_to_key<D: Digest + FixedOutputReset>(password: &[u8], salt: &[u8], out: &mut [u8]) {
+    let mut hasher = D::new();
+    let mut previous = Zeroizing::new(Vec::new());
+    for chunk in out.chunks_mut(<D as Digest>::output_size()) {
+        Digest::update(&mut hasher, &previous[..]);
+        Digest::update(&mut hasher, password);
+        Digest::update(&mut hasher, salt);
+        *previous = Digest::finalize_reset(&mut hasher).to_vec();
+        chunk.copy_from_slice(&previous[..chunk.len()]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWORD: &[u8] = b"foobar";
+    const SALT: &[u8; SALT_SIZE] = &[1, 2, 3, 4, 5, 6, 7, 8];
+    const PLAIN: &[u8] = include_bytes!("../testdata/openssl/plain.txt");
+
+    // Generated by testdata/openssl/generate.sh.
+    const FIXTURES: &[(OpensslMode, OpensslKdf, &[u8])] = &[
+        (OpensslMode::Cbc, OpensslKdf::BytesToKey(OpensslDigest::Md5), include_bytes!("../testdata/openssl/cbc-md5.bin")),
+        (OpensslMode::Cbc, OpensslKdf::BytesToKey(OpensslDigest::Sha256), include_bytes!("../testdata/openssl/cbc-sha256.bin")),
+        (OpensslMode::Cbc, PBKDF2, include_bytes!("../testdata/openssl/cbc-pbkdf2-sha256.bin")),
+        (OpensslMode::Cfb64, OpensslKdf::BytesToKey(OpensslDigest::Md5), include_bytes!("../testdata/openssl/cfb-md5.bin")),
+        (OpensslMode::Cfb64, OpensslKdf::BytesToKey(OpensslDigest::Sha256), include_bytes!("../testdata/openssl/cfb-sha256.bin")),
+        (OpensslMode::Cfb64, PBKDF2, include_bytes!("../testdata/openssl/cfb-pbkdf2-sha256.bin")),
+        (OpensslMode::Ofb64, OpensslKdf::BytesToKey(OpensslDigest::Md5), include_bytes!("../testdata/openssl/ofb-md5.bin")),
+        (OpensslMode::Ofb64, OpensslKdf::BytesToKey(OpensslDigest::Sha256), include_bytes!("../testdata/openssl/ofb-sha256.bin")),
+        (OpensslMode::Ofb64, PBKDF2, include_bytes!("../testdata/openssl/ofb-pbkdf2-sha256.bin")),
+    ];
+    const PBKDF2: OpensslKdf = OpensslKdf::Pbkdf2 { digest: OpensslDigest::Sha256, iterations: 1000 };
+
+    #[test]
+    fn test_fixtures() {
+        for &(mode, kdf, encrypted) in FIXTURES {
+            let enc = OpensslEnc::new(mode, kdf);
+            assert_eq!(enc.decrypt(PASSWORD, encrypted).as_deref(), Ok(PLAIN), "{:?} {:?}", mode, kdf);
+            assert_eq!(enc.encrypt_with_salt(PASSWORD, SALT, PLAIN).as_deref(), Ok(encrypted), "{:?} {:?}", mode, kdf);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for mode in [OpensslMode::Cbc, OpensslMode::Cfb64, OpensslMode::Ofb64] {
+            let enc = OpensslEnc::new(mode, OpensslKdf::Pbkdf2 { digest: OpensslDigest::Md5, iterations: 10 });
+            for len in [0, 1, 7, 8, 9, 16, PLAIN.len()] {
+                let encrypted = enc.encrypt(PASSWORD, &PLAIN[..len]).unwrap();
+                assert_eq!(enc.decrypt(PASSWORD, &encrypted).as_deref(), Ok(&PLAIN[..len]), "{:?} {}", mode, len);
+            }
+        }
+        assert_ne!(OpensslEnc::default().encrypt(PASSWORD, PLAIN), OpensslEnc::default().encrypt(PASSWORD, PLAIN));
+    }
+
+    #[test]
+    fn test_errors() {
+        let enc = OpensslEnc::default();
+        let encrypted = FIXTURES[1].2;
+        assert_eq!(enc.decrypt(PASSWORD, b"Salted"), Err(Error::Truncated));
+        assert_eq!(enc.decrypt(PASSWORD, &encrypted[..HEADER_SIZE]), Err(Error::Truncated));
+        assert_eq!(enc.decrypt(PASSWORD, &encrypted[..encrypted.len() - 1]), Err(Error::NotBlockAligned(55)));
+        assert_eq!(enc.decrypt(PASSWORD, &encrypted[MAGIC.len()..]), Err(Error::InvalidHeader));
+        assert_eq!(enc.decrypt(b"wrong", encrypted), Err(Error::InvalidPadding));
+    }
+}
@@ -0,0 +1,129 @@
+use crate::cts::BlowfishKey;
+use crate::encoding::Encoding;
+use crate::error::{Error, Result};
+use ripemd::Ripemd256;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+/// How the CTS feedback is started for each message.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// The feedback starts from a fixed all-ones block, exactly like blowfishj. Equal
+    /// plaintexts under the same key give equal ciphertexts.
+    #[default]
+    Legacy,
+    /// The feedback starts from a random 8-byte IV drawn for every message. The output is
+    /// the IV followed by the CTS ciphertext, so it is 8 bytes longer than the plaintext
+    /// and cannot be read by blowfishj.
+    Randomized,
+    /// Like `Randomized`, but the ciphertext is wrapped in a versioned envelope and
+    /// authenticated with HMAC-SHA256 (encrypt-then-MAC). Separate encryption and MAC keys
+    /// are derived from the password, and decryption fails with
+    /// [`Error::AuthenticationFailed`](crate::Error::AuthenticationFailed) before anything
+    /// is decrypted if the password is wrong or the data was modified.
+    Authenticated,
+}
+
+/// How the password is turned into the Blowfish key.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyDerivation {
+    /// The RIPEMD-256 hash of the password, as in current blowfishj releases.
+    #[default]
+    Ripemd256,
+    /// The SHA-1 hash of the password, as in older blowfishj releases.
+    Sha1,
+    /// The SHA-256 hash of the password.
+    Sha256,
+    /// The password bytes themselves, which must be 4 to 56 bytes long.
+    Raw,
+}
+
+impl KeyDerivation {
+    pub(crate) fn derive(&self, key: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        let derived = match self {
+            KeyDerivation::Ripemd256 => Ripemd256::digest(key).to_vec(),
+            KeyDerivation::Sha1 => Sha1::digest(key).to_vec(),
+            KeyDerivation::Sha256 => Sha256::digest(key).to_vec(),
+            KeyDerivation::Raw if key.is_empty() => return Err(Error::EmptyKey),
+            KeyDerivation::Raw if !(4..=56).contains(&key.len()) => return Err(Error::InvalidKeyLength(key.len())),
+            KeyDerivation::Raw => key.to_vec(),
+        };
+        Ok(Zeroizing::new(derived))
+    }
+}
+
+/// Settings for [`encrypt_with`](crate::encrypt_with) and the other `_with` functions.
+/// The default is compatible with blowfishj.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Options {
+    pub(crate) mode: Mode,
+    pub(crate) key_derivation: KeyDerivation,
+    pub(crate) reject_weak_keys: bool,
+    pub(crate) encoding: Encoding,
+    pub(crate) detect_encoding: bool,
+    pub(crate) detect_salted: bool,
+    pub(crate) lossy: bool,
+}
+
+impl Options {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn key_derivation(mut self, key_derivation: KeyDerivation) -> Self {
+        self.key_derivation = key_derivation;
+        self
+    }
+
+    /// Selects how the string functions write ciphertext, and how they read it unless
+    /// [`detect_encoding`](Self::detect_encoding) is set. Uppercase hex by default.
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Makes the string functions guess the encoding of ciphertext with
+    /// [`Encoding::detect`], which also skips whitespace.
+    pub fn detect_encoding(mut self, detect: bool) -> Self {
+        self.detect_encoding = detect;
+        self
+    }
+
+    /// Makes the string functions read salted strings from
+    /// [`encrypt_salted_with`](crate::encrypt_salted_with), which are recognized by their
+    /// `$bfj$` header. Off by default: the header picks the password hash and its cost, so a
+    /// forged one can make a single call take seconds and a lot of memory. Only enable this
+    /// for ciphertext from a trusted source.
+    pub fn detect_salted(mut self, detect: bool) -> Self {
+        self.detect_salted = detect;
+        self
+    }
+
+    /// Makes the string functions replace malformed text with U+FFFD instead of failing
+    /// with [`Error::InvalidUtf8`](crate::Error::InvalidUtf8) or
+    /// [`Error::InvalidText`](crate::Error::InvalidText). A wrong key then gives garbage
+    /// instead of an error.
+    pub fn lossy(mut self, lossy: bool) -> Self {
+        self.lossy = lossy;
+        self
+    }
+
+    /// Fails with [`Error::WeakKey`](crate::Error::WeakKey) instead of using a key that
+    /// expands to S-boxes with duplicate entries.
+    pub fn reject_weak_keys(mut self, reject: bool) -> Self {
+        self.reject_weak_keys = reject;
+        self
+    }
+}
+
+impl Options {
+    pub(crate) fn new_key(&self, key: &[u8]) -> Result<BlowfishKey> {
+        BlowfishKey::new_prv(key, self.key_derivation, self.reject_weak_keys)
+    }
+}
@@ -0,0 +1,188 @@
+use crate::cbc::BlowfishCBC;
+use crate::consts::*;
+use crate::ecb::BlowfishECB;
+use crate::error::{Error, Result};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+// mcrypt's Blowfish takes at most 448-bit keys. OpenSSL's Blowfish key length is 128 bits,
+// and PHP zero-extends shorter keys to it.
+const MCRYPT_MAX_KEY_SIZE: usize = 56;
+const OPENSSL_KEY_SIZE: usize = 16;
+
+/// The PHP cipher mode, `MCRYPT_MODE_ECB` or `'bf-ecb'`, and `MCRYPT_MODE_CBC` or
+/// `'bf-cbc'` with its IV.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PhpMode {
+    #[default]
+    Ecb,
+    Cbc([u8; BLOCK_SIZE]),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    Mcrypt,
+    Openssl,
+}
+
+/// Blowfish as a legacy PHP service used it, with raw keys and NUL padding.
+///
+/// [`mcrypt`](Self::mcrypt) reproduces `mcrypt_encrypt(MCRYPT_BLOWFISH, ...)`, and
+/// [`openssl`](Self::openssl) reproduces `openssl_encrypt` with `'bf-ecb'` or `'bf-cbc'`,
+/// `OPENSSL_RAW_DATA | OPENSSL_ZERO_PADDING` and the data padded with NULs beforehand. The
+/// two differ in two ways: OpenSSL zero-extends keys shorter than 16 bytes, so the same key
+/// gives different ciphertext, and mcrypt encrypts an empty string to one block of NULs.
+///
+/// Decryption strips all trailing NULs, as the PHP code did with `rtrim`, so plaintext that
+/// ended in NUL bytes loses them.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct PhpBlowfish {
+    ecb: BlowfishECB,
+    #[zeroize(skip)]
+    mode: PhpMode,
+    #[zeroize(skip)]
+    backend: Backend,
+}
+
+impl PhpBlowfish {
+    /// Uses `key` as is. Fails with [`Error::InvalidKeyLength`] for keys longer than 56
+    /// bytes, which mcrypt rejects.
+    pub fn mcrypt(mode: PhpMode, key: &[u8]) -> Result<Self> {
+        if key.len() > MCRYPT_MAX_KEY_SIZE {
+            return Err(Error::InvalidKeyLength(key.len()));
+        }
+        Ok(Self { ecb: BlowfishECB::with_key(key)?, mode, backend: Backend::Mcrypt })
+    }
+
+    /// Zero-extends keys shorter than 16 bytes and uses longer keys in full, as PHP does
+    /// for variable-length OpenSSL ciphers.
+    pub fn openssl(mode: PhpMode, key: &[u8]) -> Result<Self> {
+        if key.is_empty() {
+            return Err(Error::EmptyKey);
+        }
+        let mut extended = Zeroizing::new(key.to_vec());
+        if extended.len() < OPENSSL_KEY_SIZE {
+            extended.resize(OPENSSL_KEY_SIZE, 0);
+        }
+        Ok(Self { ecb: BlowfishECB::with_key(&extended)?, mode, backend: Backend::Openssl })
+    }
+
+    /// Pads `data` with NULs to whole blocks and encrypts it.
+    pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut len = data.len().next_multiple_of(BLOCK_SIZE);
+        if len == 0 && self.backend == Backend::Mcrypt {
+            len = BLOCK_SIZE;
+        }
+        let mut encrypted_buf = data.to_vec();
+        encrypted_buf.resize(len, 0);
+        self.crypt(&mut encrypted_buf, true)?;
+        Ok(encrypted_buf)
+    }
+
+    /// Decrypts `data` and strips trailing NULs. OpenSSL fails with
+    /// [`Error::NotBlockAligned`] on partial blocks, while mcrypt zero-extends them.
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut decrypted_buf = data.to_vec();
+        match self.backend {
+            Backend::Mcrypt => decrypted_buf.resize(data.len().next_multiple_of(BLOCK_SIZE), 0),
+            Backend::Openssl if !data.len().is_multiple_of(BLOCK_SIZE) => {
+                return Err(Error::NotBlockAligned(data.len()));
+            }
+            Backend::Openssl => {}
+        }
+        self.crypt(&mut decrypted_buf, false)?;
+
+        let len = decrypted_buf.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+        decrypted_buf[len..].zeroize();
+        decrypted_buf.truncate(len);
+        Ok(decrypted_buf)
+    }
+
+    fn crypt(&self, data: &mut [u8], encrypt: bool) -> Result<()> {
+        match (self.mode, encrypt) {
+            (PhpMode::Ecb, true) => self.ecb.encrypt_blocks(data),
+            (PhpMode::Ecb, false) => self.ecb.decrypt_blocks(data),
+            (PhpMode::Cbc(iv), encrypt) => {
+                let input = Zeroizing::new(data.to_vec());
+                let mut cbc = BlowfishCBC::from_ecb(self.ecb.clone(), iv);
+                if encrypt {
+                    cbc.encrypt(&input, 0, data, 0, input.len())?;
+                } else {
+                    cbc.decrypt(&input, 0, data, 0, input.len())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &[u8] = b"How I wish I could recollect PI easily";
+    const IV: [u8; BLOCK_SIZE] = [0xfe, 0xdc, 0xba, 0x98, 0x76, 0x54, 0x32, 0x10];
+    const SHORT_KEY: &[u8] = b"secret";
+    const LONG_KEY: &[u8] = b"a much longer key!!!";
+
+    // Computed with Python's Blowfish from the mcrypt and PHP OpenSSL rules. The SHORT_KEY
+    // OpenSSL values also match `openssl enc -bf-ecb -nopad -K <key>`. The LONG_KEY ones come
+    // from Python alone: PHP sets the key length to all 20 bytes, while the `openssl` CLI
+    // cuts `-K` to 16 bytes and gives different ciphertext.
+    const FIXTURES: &[(bool, PhpMode, &[u8], &str)] = &[
+        (true, PhpMode::Ecb, SHORT_KEY, "D84907687BCE02FE6EF32709E3947B349AAEDDB9600FC493F866FB2FD643D623C51C796DEC559066"),
+        (false, PhpMode::Ecb, SHORT_KEY, "E6153DC7D493A591DECA6A67C3A4DEF91DB43257894B7C13BC22E01F9CE377C2D0F17A1FFB09BECC"),
+        (true, PhpMode::Cbc(IV), SHORT_KEY, "7D02EFDF4FD9052094F30004003A207F28A2E33474686930C6E0BE12833900BE68B7F3CD5636F22B"),
+        (false, PhpMode::Cbc(IV), SHORT_KEY, "1C5FCCA8B9A6CE9B3FDCC730B7BFAE25BE68BB25E16CD98074343C79D4FA3430D53943E94D936EEE"),
+        (true, PhpMode::Ecb, LONG_KEY, "17A98F719BB04B7A982D9975092B40F5DF05817AB48D4623B989A1DD7383A03A2B140BA74003A436"),
+        (false, PhpMode::Ecb, LONG_KEY, "17A98F719BB04B7A982D9975092B40F5DF05817AB48D4623B989A1DD7383A03A2B140BA74003A436"),
+        (true, PhpMode::Cbc(IV), LONG_KEY, "D0F11F60E0FD0FABFED811B13EECDC2A179C4F4F8D48171ABFBD4D96FCBD279D58A29BA4A608AD56"),
+        (false, PhpMode::Cbc(IV), LONG_KEY, "D0F11F60E0FD0FABFED811B13EECDC2A179C4F4F8D48171ABFBD4D96FCBD279D58A29BA4A608AD56"),
+    ];
+
+    fn cipher(mcrypt: bool, mode: PhpMode, key: &[u8]) -> PhpBlowfish {
+        if mcrypt {
+            PhpBlowfish::mcrypt(mode, key).unwrap()
+        } else {
+            PhpBlowfish::openssl(mode, key).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_fixtures() {
+        for &(mcrypt, mode, key, encrypted) in FIXTURES {
+            let cipher = cipher(mcrypt, mode, key);
+            let encrypted = hex::decode(encrypted).unwrap();
+            assert_eq!(cipher.encrypt(TEXT), Ok(encrypted.clone()), "mcrypt {} {:?}", mcrypt, mode);
+            assert_eq!(cipher.decrypt(&encrypted).as_deref(), Ok(TEXT), "mcrypt {} {:?}", mcrypt, mode);
+        }
+    }
+
+    #[test]
+    fn test_empty_and_nuls() {
+        let mcrypt = PhpBlowfish::mcrypt(PhpMode::Ecb, SHORT_KEY).unwrap();
+        let openssl = PhpBlowfish::openssl(PhpMode::Ecb, SHORT_KEY).unwrap();
+        assert_eq!(mcrypt.encrypt(b"").map(hex::encode_upper).as_deref(), Ok("010A0D9149750A32"));
+        assert_eq!(openssl.encrypt(b""), Ok(Vec::new()));
+        assert_eq!(mcrypt.decrypt(&mcrypt.encrypt(b"").unwrap()), Ok(Vec::new()));
+
+        let encrypted = openssl.encrypt(b"a\0b\0\0").unwrap();
+        assert_eq!(openssl.decrypt(&encrypted).as_deref(), Ok(&b"a\0b"[..]));
+    }
+
+    #[test]
+    fn test_partial_blocks() {
+        let mcrypt = PhpBlowfish::mcrypt(PhpMode::Ecb, LONG_KEY).unwrap();
+        let openssl = PhpBlowfish::openssl(PhpMode::Ecb, LONG_KEY).unwrap();
+        let encrypted = mcrypt.encrypt(b"1234567").unwrap();
+        assert_eq!(openssl.decrypt(&encrypted[..5]), Err(Error::NotBlockAligned(5)));
+        assert!(mcrypt.decrypt(&encrypted[..5]).is_ok());
+    }
+
+    #[test]
+    fn test_keys() {
+        assert_eq!(PhpBlowfish::mcrypt(PhpMode::Ecb, &[1; 57]).err(), Some(Error::InvalidKeyLength(57)));
+        assert!(PhpBlowfish::openssl(PhpMode::Ecb, &[1; 57]).is_ok());
+        assert_eq!(PhpBlowfish::mcrypt(PhpMode::Ecb, b"").err(), Some(Error::EmptyKey));
+        assert_eq!(PhpBlowfish::openssl(PhpMode::Ecb, b"").err(), Some(Error::EmptyKey));
+    }
+}
@@ -0,0 +1,117 @@
+//! RustCrypto `cipher` trait implementations, so that [`BlowfishECB`] can be used with
+//! the `cbc`, `cfb-mode`, `ctr` and other mode crates.
+
+use crate::ecb::BlowfishECB;
+use cipher::consts::{U56, U8};
+use cipher::{AlgorithmName, BlockCipher, InvalidLength, Key, KeyInit, KeySizeUser};
+use std::fmt;
+
+// The key lengths accepted by the `blowfish` crate.
+const MIN_KEY_SIZE: usize = 4;
+const MAX_KEY_SIZE: usize = 56;
+
+impl KeySizeUser for BlowfishECB {
+    type KeySize = U56;
+}
+
+impl KeyInit for BlowfishECB {
+    fn new(key: &Key<Self>) -> Self {
+        Self::with_key(key).unwrap()
+    }
+
+    /// Accepts keys of 4 to 56 bytes, like the `blowfish` crate. Use
+    /// [`BlowfishECB::with_key`] for other lengths.
+    fn new_from_slice(key: &[u8]) -> Result<Self, InvalidLength> {
+        if !(MIN_KEY_SIZE..=MAX_KEY_SIZE).contains(&key.len()) {
+            return Err(InvalidLength);
+        }
+        Self::with_key(key).map_err(|_| InvalidLength)
+    }
+}
+
+impl BlockCipher for BlowfishECB {}
+
+cipher::impl_simple_block_encdec!(
+    <> BlowfishECB, U8, ecb, block,
+    encrypt: {
+        let mut buf = block.clone_in().into();
+        ecb.encrypt_block(&mut buf);
+        *block.get_out() = buf.into();
+    }
+    decrypt: {
+        let mut buf = block.clone_in().into();
+        ecb.decrypt_block(&mut buf);
+        *block.get_out() = buf.into();
+    }
+);
+
+impl AlgorithmName for BlowfishECB {
+    fn write_alg_name(f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Blowfish")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::ECB_VECTORS;
+    use cipher::{Block, BlockDecrypt, BlockEncrypt, BlockEncryptMut, InnerIvInit};
+
+    #[test]
+    fn test_matches_own_ecb_and_blowfish_crate() {
+        for &(key, plain, encrypted) in ECB_VECTORS.iter() {
+            let key = key.to_be_bytes();
+            let ours = <BlowfishECB as KeyInit>::new_from_slice(&key).unwrap();
+            let theirs = <blowfish::Blowfish as KeyInit>::new_from_slice(&key).unwrap();
+
+            let mut block = Block::<BlowfishECB>::from(plain.to_be_bytes());
+            BlockEncrypt::encrypt_block(&ours, &mut block);
+            assert_eq!(block.as_slice(), encrypted.to_be_bytes());
+            assert_eq!(BlowfishECB::with_key(&key).unwrap().encrypt_u64(plain), encrypted);
+
+            let mut expected = Block::<blowfish::Blowfish>::from(plain.to_be_bytes());
+            theirs.encrypt_block(&mut expected);
+            assert_eq!(block, expected);
+
+            BlockDecrypt::decrypt_block(&ours, &mut block);
+            assert_eq!(block.as_slice(), plain.to_be_bytes());
+        }
+    }
+
+    #[test]
+    fn test_key_lengths() {
+        let key: Vec<u8> = (1..=56).collect();
+        let plain = Block::<BlowfishECB>::from(0x0123456789abcdefu64.to_be_bytes());
+        for len in 4..=56 {
+            let ours = <BlowfishECB as KeyInit>::new_from_slice(&key[..len]).unwrap();
+            let theirs = <blowfish::Blowfish as KeyInit>::new_from_slice(&key[..len]).unwrap();
+            let (mut a, mut b) = (plain, plain);
+            BlockEncrypt::encrypt_block(&ours, &mut a);
+            theirs.encrypt_block(&mut b);
+            assert_eq!(a, b, "key length {}", len);
+        }
+        for len in [0, 3, 57, 100] {
+            let key = vec![1u8; len];
+            assert!(<BlowfishECB as KeyInit>::new_from_slice(&key).is_err(), "key length {}", len);
+            assert!(<blowfish::Blowfish as KeyInit>::new_from_slice(&key).is_err(), "key length {}", len);
+        }
+    }
+
+    #[test]
+    fn test_cbc_mode() {
+        // Eric Young's chaining test vector.
+        let key = hex::decode("0123456789abcdeff0e1d2c3b4a59687").unwrap();
+        let iv = hex::decode("fedcba9876543210").unwrap();
+        let mut data = *b"7654321 Now is the time for \0\0\0\0";
+
+        let ecb = <BlowfishECB as KeyInit>::new_from_slice(&key).unwrap();
+        let mut enc = cbc::Encryptor::<BlowfishECB>::inner_iv_slice_init(ecb, &iv).unwrap();
+        for block in data.chunks_exact_mut(8) {
+            enc.encrypt_block_mut(Block::<BlowfishECB>::from_mut_slice(block));
+        }
+        assert_eq!(
+            hex::encode(data),
+            "6b77b4d63006dee605b156e27403979358deb9e7154616d959f1652bd5ff92cc"
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use crate::blowfishj::{decrypt_bytes_with, encrypt_bytes_with};
+use crate::cts::random_iv;
+use crate::encoding::{decode_text, encode_text};
+use crate::error::{Error, Result};
+use crate::options::{KeyDerivation, Mode, Options};
+use argon2::{Algorithm, Argon2, Params, Version};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+// Layout: $bfj$<algorithm>$<parameters>$<mode>$<salt in hex>$<encoded ciphertext>
+const PREFIX: &str = "$bfj$";
+const SALT_SIZE: usize = 16;
+const KEY_SIZE: usize = 32;
+
+// Upper bounds for the parameters, so that a forged header cannot ask for unbounded work.
+// Encrypting checks them too, so that it never writes a header that decrypting refuses. A
+// header at these limits still takes about a GiB of memory and minutes of CPU, which is
+// why reading salted strings is opt-in.
+const MAX_PBKDF2_ITERATIONS: u32 = 10_000_000;
+const MAX_ARGON2_MEMORY_KIB: u32 = 1 << 20;
+const MAX_ARGON2_ITERATIONS: u32 = 100;
+const MAX_ARGON2_PARALLELISM: u32 = 16;
+
+/// A salted, deliberately slow password hash for [`encrypt_salted`](crate::encrypt_salted).
+///
+/// The algorithm, its parameters, the mode and a random 16-byte salt are stored in front of
+/// the ciphertext, so [`decrypt_with`](crate::decrypt_with) needs nothing but the password
+/// once [`Options::detect_salted`] is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordHash {
+    /// PBKDF2-HMAC-SHA256 with the given number of iterations.
+    Pbkdf2Sha256 { iterations: u32 },
+    /// Argon2id (version 0x13) with the given memory in KiB, passes and lanes.
+    Argon2id { memory_kib: u32, iterations: u32, parallelism: u32 },
+}
+
+/// Argon2id with 19 MiB of memory and 2 passes, as recommended by OWASP.
+impl Default for PasswordHash {
+    fn default() -> Self {
+        PasswordHash::Argon2id { memory_kib: 19 * 1024, iterations: 2, parallelism: 1 }
+    }
+}
+
+impl PasswordHash {
+    /// Checks the parameters against the limits above and Argon2's own minimums.
+    fn validate(&self) -> Result<()> {
+        let valid = match *self {
+            PasswordHash::Pbkdf2Sha256 { iterations } => (1..=MAX_PBKDF2_ITERATIONS).contains(&iterations),
+            PasswordHash::Argon2id { memory_kib, iterations, parallelism } => {
+                memory_kib <= MAX_ARGON2_MEMORY_KIB
+                    && iterations <= MAX_ARGON2_ITERATIONS
+                    && parallelism <= MAX_ARGON2_PARALLELISM
+                    && Params::new(memory_kib, iterations, parallelism, Some(KEY_SIZE)).is_ok()
+            }
+        };
+        if !valid {
+            return Err(Error::InvalidPasswordHash);
+        }
+        Ok(())
+    }
+
+    /// Derives the key; the parameters must have been validated.
+    fn derive(&self, key: &[u8], salt: &[u8]) -> Result<Zeroizing<[u8; KEY_SIZE]>> {
+        if key.is_empty() {
+            return Err(Error::EmptyKey);
+        }
+
+        let mut derived = Zeroizing::new([0u8; KEY_SIZE]);
+        match *self {
+            PasswordHash::Pbkdf2Sha256 { iterations } => {
+                pbkdf2::pbkdf2_hmac::<Sha256>(key, salt, iterations, &mut derived[..]);
+            }
+            PasswordHash::Argon2id { memory_kib, iterations, parallelism } => {
+                let params = Params::new(memory_kib, iterations, parallelism, Some(KEY_SIZE))
+                    .map_err(|_| Error::InvalidPasswordHash)?;
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password_into(key, salt, &mut derived[..])
+                    .map_err(|_| Error::InvalidPasswordHash)?;
+            }
+        }
+        Ok(derived)
+    }
+
+    fn write_header(&self, mode: Mode, salt: &[u8]) -> String {
+        let (name, params) = match *self {
+            PasswordHash::Pbkdf2Sha256 { iterations } => ("pbkdf2-sha256", format!("i={}", iterations)),
+            PasswordHash::Argon2id { memory_kib, iterations, parallelism } => {
+                ("argon2id", format!("m={},t={},p={}", memory_kib, iterations, parallelism))
+            }
+        };
+        format!("{}{}${}${}${}$", PREFIX, name, params, mode_name(mode), hex::encode_upper(salt))
+    }
+
+    fn parse(name: &str, params: &str) -> Result<Self> {
+        match name {
+            "pbkdf2-sha256" => {
+                let [iterations] = parse_params(params, ["i"])?;
+                Ok(PasswordHash::Pbkdf2Sha256 { iterations })
+            }
+            "argon2id" => {
+                let [memory_kib, iterations, parallelism] = parse_params(params, ["m", "t", "p"])?;
+                Ok(PasswordHash::Argon2id { memory_kib, iterations, parallelism })
+            }
+            _ => Err(Error::InvalidHeader),
+        }
+    }
+}
+
+fn mode_name(mode: Mode) -> &'static str {
+    match mode {
+        Mode::Legacy => "legacy",
+        Mode::Randomized => "randomized",
+        Mode::Authenticated => "authenticated",
+    }
+}
+
+fn parse_mode(name: &str) -> Result<Mode> {
+    match name {
+        "legacy" => Ok(Mode::Legacy),
+        "randomized" => Ok(Mode::Randomized),
+        "authenticated" => Ok(Mode::Authenticated),
+        _ => Err(Error::InvalidHeader),
+    }
+}
+
+/// Parses `a=1,b=2` with exactly the given names in the given order.
+fn parse_params<const N: usize>(params: &str, names: [&str; N]) -> Result<[u32; N]> {
+    let mut values = [0; N];
+    let mut parts = params.split(',');
+    for (value, name) in values.iter_mut().zip(names) {
+        let (key, digits) = parts.next().and_then(|part| part.split_once('=')).ok_or(Error::InvalidHeader)?;
+        if key != name || digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(Error::InvalidHeader);
+        }
+        *value = digits.parse().map_err(|_| Error::InvalidHeader)?;
+    }
+    if parts.next().is_some() {
+        return Err(Error::InvalidHeader);
+    }
+    Ok(values)
+}
+
+pub(crate) fn is_salted(options: &Options, src: &str) -> bool {
+    options.detect_salted && src.trim_start().starts_with(PREFIX)
+}
+
+/// Encrypts `data` with a key derived from `key` and a fresh salt. The derived key is used
+/// as the raw Blowfish key, in the mode selected by `options`.
+pub(crate) fn encrypt(options: &Options, hash: PasswordHash, key: &[u8], data: &[u8]) -> Result<String> {
+    hash.validate()?;
+    let mut salt = [0u8; SALT_SIZE];
+    salt[..8].copy_from_slice(&random_iv()?);
+    salt[8..].copy_from_slice(&random_iv()?);
+
+    let derived = hash.derive(key, &salt)?;
+    let options = options.key_derivation(KeyDerivation::Raw);
+    let encrypted_buf = encrypt_bytes_with(&options, &derived[..], data)?;
+    Ok(hash.write_header(options.mode, &salt) + &encode_text(&options, &encrypted_buf)?)
+}
+
+/// Decrypts in the mode named by the header, whatever the mode of `options`.
+pub(crate) fn decrypt(options: &Options, key: &[u8], src: &str) -> Result<Vec<u8>> {
+    let rest = src.trim().strip_prefix(PREFIX).ok_or(Error::InvalidHeader)?;
+    let fields: Vec<&str> = rest.splitn(5, '$').collect();
+    let &[name, params, mode, salt, body] = &fields[..] else {
+        return Err(Error::InvalidHeader);
+    };
+
+    let hash = PasswordHash::parse(name, params)?;
+    hash.validate().map_err(|_| Error::InvalidHeader)?;
+    let mode = parse_mode(mode)?;
+    let salt = hex::decode(salt).map_err(|_| Error::InvalidHeader)?;
+    if salt.len() != SALT_SIZE {
+        return Err(Error::InvalidHeader);
+    }
+    let buf = decode_text(options, body)?;
+
+    let derived = hash.derive(key, &salt)?;
+    let options = options.mode(mode).key_derivation(KeyDerivation::Raw);
+    decrypt_bytes_with(&options, &derived[..], &buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"foobar";
+    const TEXT: &[u8] = b"How I wish I could recollect PI easily using one trick?";
+    const FAST_PBKDF2: PasswordHash = PasswordHash::Pbkdf2Sha256 { iterations: 1000 };
+    const FAST_ARGON2: PasswordHash = PasswordHash::Argon2id { memory_kib: 64, iterations: 2, parallelism: 1 };
+
+    fn options() -> Options {
+        Options::new().detect_salted(true)
+    }
+
+    #[test]
+    fn test_fixtures() {
+        let fixtures = [
+            "$bfj$pbkdf2-sha256$i=1000$legacy$000102030405060708090A0B0C0D0E0F$B91AF4475B1B6F57BFA2709D2A2226D5E86742E93203B34816BEB7902690D33697CE17AA2069A916B7F3B4A2A32CACA9829982505395F8",
+            "$bfj$argon2id$m=64,t=2,p=1$legacy$000102030405060708090A0B0C0D0E0F$C0D6BD577D8C45268E6DA08A41D36E12F02BECFC5FE77F3722A6ECF2F113BAC576789315EE9400582FD21D21801E0A4CC7A221C465A8CA",
+        ];
+        for fixture in fixtures {
+            assert!(is_salted(&options(), fixture));
+            assert!(!is_salted(&Options::default(), fixture));
+            assert_eq!(decrypt(&options(), KEY, fixture), Ok(TEXT.to_vec()), "{}", fixture);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for hash in [FAST_PBKDF2, FAST_ARGON2] {
+            let encrypted = encrypt(&options(), hash, KEY, TEXT).unwrap();
+            assert!(is_salted(&options(), &encrypted));
+            assert_eq!(decrypt(&options(), KEY, &encrypted), Ok(TEXT.to_vec()));
+            assert_ne!(encrypt(&options(), hash, KEY, TEXT).unwrap(), encrypted);
+        }
+        assert_eq!(encrypt(&Options::default(), FAST_PBKDF2, b"", TEXT), Err(Error::EmptyKey));
+    }
+
+    #[test]
+    fn test_invalid_parameters() {
+        let hashes = [
+            PasswordHash::Pbkdf2Sha256 { iterations: 0 },
+            PasswordHash::Pbkdf2Sha256 { iterations: MAX_PBKDF2_ITERATIONS + 1 },
+            PasswordHash::Argon2id { memory_kib: MAX_ARGON2_MEMORY_KIB + 1, iterations: 2, parallelism: 1 },
+            PasswordHash::Argon2id { memory_kib: 64, iterations: MAX_ARGON2_ITERATIONS + 1, parallelism: 1 },
+            PasswordHash::Argon2id { memory_kib: 64, iterations: 2, parallelism: MAX_ARGON2_PARALLELISM + 1 },
+            PasswordHash::Argon2id { memory_kib: 64, iterations: 0, parallelism: 1 },
+            PasswordHash::Argon2id { memory_kib: 64, iterations: 2, parallelism: 0 },
+            PasswordHash::Argon2id { memory_kib: 1, iterations: 2, parallelism: 1 },
+        ];
+        for hash in hashes {
+            assert_eq!(encrypt(&options(), hash, KEY, TEXT), Err(Error::InvalidPasswordHash), "{:?}", hash);
+            assert_eq!(encrypt(&options(), hash, b"", TEXT), Err(Error::InvalidPasswordHash), "{:?}", hash);
+        }
+        assert!(PasswordHash::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_mode_in_header() {
+        for mode in [Mode::Legacy, Mode::Randomized, Mode::Authenticated] {
+            let encrypted = encrypt(&options().mode(mode), FAST_PBKDF2, KEY, TEXT).unwrap();
+            assert!(encrypted.contains(&format!("${}$", mode_name(mode))));
+            for other in [Mode::Legacy, Mode::Randomized, Mode::Authenticated] {
+                assert_eq!(decrypt(&options().mode(other), KEY, &encrypted), Ok(TEXT.to_vec()), "{:?} {:?}", mode, other);
+            }
+        }
+
+        let sealed = encrypt(&options().mode(Mode::Authenticated), FAST_PBKDF2, KEY, TEXT).unwrap();
+        let tampered = format!("{}{}", &sealed[..sealed.len() - 1], if sealed.ends_with('0') { '1' } else { '0' });
+        assert_eq!(decrypt(&options(), KEY, &tampered), Err(Error::AuthenticationFailed));
+    }
+
+    #[test]
+    fn test_malformed_headers() {
+        let salt = "000102030405060708090A0B0C0D0E0F";
+        let headers = [
+            "$bfj$".to_string(),
+            format!("$bfj$pbkdf2-sha256$i=1000$legacy${}", salt),
+            format!("$bfj$pbkdf2-sha256$i=1000${}$00", salt),
+            format!("$bfj$pbkdf2-sha256$i=1000$ctr${}$00", salt),
+            format!("$bfj$scrypt$n=1024$legacy${}$00", salt),
+            format!("$bfj$pbkdf2-sha256$i=0$legacy${}$00", salt),
+            format!("$bfj$pbkdf2-sha256$i=+5$legacy${}$00", salt),
+            format!("$bfj$pbkdf2-sha256$i=99999999999$legacy${}$00", salt),
+            format!("$bfj$pbkdf2-sha256$i=1000,x=1$legacy${}$00", salt),
+            format!("$bfj$argon2id$t=2,m=64,p=1$legacy${}$00", salt),
+            format!("$bfj$argon2id$m=4000000,t=2,p=1$legacy${}$00", salt),
+            "$bfj$pbkdf2-sha256$i=1000$legacy$0001$00".to_string(),
+            "$bfj$pbkdf2-sha256$i=1000$legacy$XYZ$00".to_string(),
+        ];
+        for header in &headers {
+            assert_eq!(decrypt(&options(), KEY, header), Err(Error::InvalidHeader), "{}", header);
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use crate::blocks::{BlockReader, BlockWriter, Decryptor, Encryptor};
+use crate::consts::*;
+use crate::cts::BlowfishCTS;
+use crate::error::Result;
+use std::io::{self, Read, Write};
+
+impl Encryptor for BlowfishCTS {
+    fn encrypt(&mut self, src: &[u8], dst: &mut [u8]) -> Result<()> {
+        self.encrypt(src, 0, dst, 0, src.len())?;
+        Ok(())
+    }
+
+    fn encrypt_last(&mut self, last: &mut [u8; BLOCK_SIZE], len: usize, dst: &mut [u8]) -> Result<usize> {
+        if len > 0 {
+            self.encrypt(&last[..len], 0, dst, 0, len)?;
+        }
+        Ok(len)
+    }
+}
+
+impl Decryptor for BlowfishCTS {
+    fn ready(&mut self, pending: &mut Vec<u8>, eof: bool) -> io::Result<usize> {
+        if eof {
+            Ok(pending.len())
+        } else {
+            Ok(pending.len() - pending.len() % BLOCK_SIZE)
+        }
+    }
+
+    fn decrypt(&mut self, src: &[u8], dst: &mut [u8]) -> Result<()> {
+        self.decrypt(src, 0, dst, 0, src.len())?;
+        Ok(())
+    }
+}
+
+/// Encrypts everything written to it and passes the ciphertext on to the inner writer.
+///
+/// Full blocks are encrypted as soon as they are complete and passed on by the next write
+/// or flush; the trailing partial block is kept back until [`CtsWriter::finish`] is called,
+/// so the output is identical to [`encrypt_bytes`](crate::encrypt_bytes) of the
+/// concatenated input. Dropping the writer finishes it as well but ignores any error, so
+/// call `finish` to see them.
+pub struct CtsWriter<W: Write>(BlockWriter<W, BlowfishCTS>);
+
+impl<W: Write> CtsWriter<W> {
+    pub fn new(key: &[u8], inner: W) -> Result<Self> {
+        Ok(Self(BlockWriter::new(BlowfishCTS::with_key(key)?, inner)))
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.0.get_ref()
+    }
+
+    /// Encrypts the trailing partial block, flushes and returns the inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        self.0.finish()
+    }
+}
+
+impl<W: Write> Write for CtsWriter<W> {
+    /// Takes some of `data` and returns how much. On error, none of it was taken.
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.0.write(data)
+    }
+
+    /// Flushes the inner writer. The trailing partial block is only written by `finish`.
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Decrypts ciphertext read from the inner reader.
+///
+/// Full blocks are decrypted as they arrive; a trailing partial block is decrypted once
+/// the inner reader reports end of file, so the output is identical to
+/// [`decrypt_bytes`](crate::decrypt_bytes) of the whole ciphertext.
+pub struct CtsReader<R: Read>(BlockReader<R, BlowfishCTS>);
+
+impl<R: Read> CtsReader<R> {
+    pub fn new(key: &[u8], inner: R) -> Result<Self> {
+        Ok(Self(BlockReader::new(BlowfishCTS::with_key(key)?, inner)))
+    }
+
+    pub fn get_ref(&self) -> &R {
+        self.0.get_ref()
+    }
+
+    pub fn into_inner(self) -> R {
+        self.0.into_inner()
+    }
+}
+
+impl<R: Read> Read for CtsReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::ChunkedReader;
+    use crate::{decrypt_bytes, encrypt_bytes};
+
+    const KEY: &[u8] = b"foobar";
+
+    /// Fails every other call, to check that failed writes take no input.
+    struct FlakyWriter {
+        out: Vec<u8>,
+        fail: bool,
+    }
+
+    impl Write for FlakyWriter {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.fail = !self.fail;
+            if self.fail {
+                return Err(io::ErrorKind::Other.into());
+            }
+            let n = data.len().min(5);
+            self.out.extend_from_slice(&data[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 + 7) as u8).collect()
+    }
+
+    #[test]
+    fn test_writer_matches_encrypt_bytes() {
+        for len in [0, 1, 7, 8, 9, 15, 16, 17, 100, 20000] {
+            let data = sample(len);
+            let expected = encrypt_bytes(KEY, &data).unwrap();
+            for chunk in [1, 3, 8, 13, 64, 10000] {
+                let mut writer = CtsWriter::new(KEY, Vec::new()).unwrap();
+                for part in data.chunks(chunk) {
+                    writer.write_all(part).unwrap();
+                }
+                let got = writer.finish().unwrap();
+                assert_eq!(got, expected, "len {} chunk {}", len, chunk);
+            }
+        }
+    }
+
+    #[test]
+    fn test_writer_finishes_on_drop() {
+        let data = sample(21);
+        let mut out = Vec::new();
+        {
+            let mut writer = CtsWriter::new(KEY, &mut out).unwrap();
+            writer.write_all(&data).unwrap();
+        }
+        assert_eq!(out, encrypt_bytes(KEY, &data).unwrap());
+    }
+
+    #[test]
+    fn test_reader_matches_decrypt_bytes() {
+        for len in [0, 1, 7, 8, 9, 15, 16, 17, 100, 20000] {
+            let encrypted = encrypt_bytes(KEY, &sample(len)).unwrap();
+            let expected = decrypt_bytes(KEY, &encrypted).unwrap();
+            for chunk in [1, 3, 8, 13, 64, 10000] {
+                let inner = ChunkedReader { data: &encrypted, chunk };
+                let mut reader = CtsReader::new(KEY, inner).unwrap();
+                let mut got = Vec::new();
+                reader.read_to_end(&mut got).unwrap();
+                assert_eq!(got, expected, "len {} chunk {}", len, chunk);
+            }
+        }
+    }
+
+    #[test]
+    fn test_failed_write_takes_no_input() {
+        let data = sample(96);
+        let mut writer = CtsWriter::new(KEY, FlakyWriter { out: Vec::new(), fail: false }).unwrap();
+        let mut rest = &data[..];
+        while !rest.is_empty() {
+            match writer.write(&rest[..rest.len().min(11)]) {
+                Ok(n) => rest = &rest[n..],
+                Err(e) => assert_eq!(e.kind(), io::ErrorKind::Other),
+            }
+        }
+        while writer.flush().is_err() {}
+        assert_eq!(writer.finish().unwrap().out, encrypt_bytes(KEY, &data).unwrap());
+    }
+}
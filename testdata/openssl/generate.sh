@@ -0,0 +1,20 @@
+#!/bin/sh
+# Regenerates the OpenSSL fixtures. Blowfish needs the legacy provider in OpenSSL 3.
+#
+# The salt is fixed with -S so that the files are reproducible. OpenSSL 3 does not write the
+# Salted__ header for an explicit salt, so it is written here, giving the same layout as
+# `openssl enc -salt` with a random salt.
+set -e
+cd "$(dirname "$0")"
+enc() {
+    out=$1
+    shift
+    printf 'Salted__\001\002\003\004\005\006\007\010' > "$out"
+    openssl enc -provider legacy -provider default -e -S 0102030405060708 -pass pass:foobar \
+        -in plain.txt "$@" 2>/dev/null >> "$out"
+}
+for mode in cbc cfb ofb; do
+    enc $mode-md5.bin -bf-$mode -md md5
+    enc $mode-sha256.bin -bf-$mode -md sha256
+    enc $mode-pbkdf2-sha256.bin -bf-$mode -pbkdf2 -iter 1000 -md sha256
+done